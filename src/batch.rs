@@ -0,0 +1,133 @@
+//! Batching records into fewer writes.
+//!
+//! A [`Logfmt`](crate::Logfmt) drain writes each record in several
+//! pieces and flushes its writer after every record. For a file or
+//! socket that sees a lot of records, the resulting syscalls can add
+//! up. Wrapping the writer in [`Batched`] collects the formatted
+//! lines in memory instead, and passes them on in a single
+//! `write_all` once enough lines have accumulated or on a fixed
+//! timer tick.
+//!
+//! This is independent of `slog-async`: that moves formatting off the
+//! logging thread, while batching reduces how often the output is
+//! written to. The two can be combined.
+//!
+//! # Example
+//! ```rust,no_run
+//! use slog::{o, Drain, Logger};
+//! use slog_logfmt::batch::Batched;
+//! use slog_logfmt::Logfmt;
+//! use std::fs::File;
+//! use std::time::Duration;
+//!
+//! let file = File::create("app.log").unwrap();
+//! let writer = Batched::new(file, 64, Duration::from_millis(100));
+//! let drain = Logfmt::new(writer).build().fuse();
+//! let drain = slog_async::Async::new(drain).build().fuse();
+//! let logger = Logger::root(drain, o!());
+//! ```
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+struct Batch<W> {
+    inner: W,
+    buf: Vec<u8>,
+    lines: usize,
+}
+
+impl<W: io::Write> Batch<W> {
+    /// Writes out and flushes everything collected so far.
+    fn write_out(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        self.lines = 0;
+        let result = self.inner.write_all(&self.buf);
+        self.buf.clear();
+        result?;
+        self.inner.flush()
+    }
+}
+
+/// Collects lines and writes them to the wrapped writer in batches.
+///
+/// Lines are passed on once `max_lines` of them have been written,
+/// on every tick of a background thread that wakes up each `window`
+/// (regardless of when the last batch was written, so lines wait at
+/// most `window` before being passed on), and when this writer is
+/// dropped. Flushing this
+/// writer does nothing, since [`Logfmt`](crate::Logfmt) flushes after
+/// every record.
+pub struct Batched<W: io::Write + Send + 'static> {
+    batch: Arc<Mutex<Batch<W>>>,
+    max_lines: usize,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<W: io::Write + Send + 'static> Batched<W> {
+    /// Wraps `inner`, writing to it after every `max_lines` lines and
+    /// at least every `window`.
+    pub fn new(inner: W, max_lines: usize, window: Duration) -> Batched<W> {
+        let batch = Arc::new(Mutex::new(Batch {
+            inner,
+            buf: Vec::new(),
+            lines: 0,
+        }));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let timed = Arc::clone(&batch);
+        let thread = thread::Builder::new()
+            .name("logfmt-batch".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(window) {
+                    if let Ok(mut batch) = timed.lock() {
+                        // There's nobody to report errors to; the next
+                        // full batch will most likely run into them, too.
+                        let _ = batch.write_out();
+                    }
+                }
+            })
+            .expect("failed to spawn the batch thread");
+        Batched {
+            batch,
+            max_lines: max_lines.max(1),
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<W: io::Write + Send + 'static> io::Write for Batched<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut batch = self
+            .batch
+            .lock()
+            .map_err(|_| io::Error::other("writer poisoned"))?;
+        batch.buf.extend_from_slice(buf);
+        batch.lines += buf.iter().filter(|&&b| b == b'\n').count();
+        if batch.lines >= self.max_lines {
+            batch.write_out()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: io::Write + Send + 'static> Drop for Batched<W> {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        if let Ok(mut batch) = self.batch.lock() {
+            let _ = batch.write_out();
+        }
+    }
+}
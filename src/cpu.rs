@@ -0,0 +1,68 @@
+//! Reading the CPU time used by the process, without depending on the
+//! `libc` crate.
+
+use std::time::Duration;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+mod clock {
+    use std::os::raw::{c_int, c_long};
+    use std::time::Duration;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const CLOCK_PROCESS_CPUTIME_ID: c_int = 2;
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const CLOCK_PROCESS_CPUTIME_ID: c_int = 12;
+    #[cfg(target_os = "freebsd")]
+    const CLOCK_PROCESS_CPUTIME_ID: c_int = 15;
+
+    #[repr(C)]
+    struct Timespec {
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    extern "C" {
+        fn clock_gettime(clock: c_int, time: *mut Timespec) -> c_int;
+    }
+
+    pub(super) fn process_cpu_time() -> Option<Duration> {
+        let mut time = Timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `time` is a valid, writable timespec, which is all
+        // that clock_gettime requires.
+        if unsafe { clock_gettime(CLOCK_PROCESS_CPUTIME_ID, &mut time) } != 0 {
+            return None;
+        }
+        Some(Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+    }
+}
+
+/// Returns the CPU time that all threads of this process have used
+/// so far, or `None` if it can't be read on this platform.
+pub(crate) fn process_cpu_time() -> Option<Duration> {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    ))]
+    let cpu_time = clock::process_cpu_time();
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd"
+    )))]
+    let cpu_time = None;
+    cpu_time
+}
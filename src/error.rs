@@ -0,0 +1,87 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// Where in the process of logging a record an error happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorContext {
+    /// Collecting the record's key/value pairs failed.
+    Serializing,
+
+    /// Writing the line prefix failed.
+    Prefix,
+
+    /// Writing the field with the given key failed.
+    Field(String),
+
+    /// Writing the line terminator failed.
+    Newline,
+
+    /// Flushing the writer failed.
+    Flush,
+
+    /// Writing the schema header failed.
+    Header,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorContext::Serializing => write!(f, "serializing the record"),
+            ErrorContext::Prefix => write!(f, "writing the prefix"),
+            ErrorContext::Field(key) => write!(f, "writing field {:?}", key),
+            ErrorContext::Newline => write!(f, "writing the line terminator"),
+            ErrorContext::Flush => write!(f, "flushing the writer"),
+            ErrorContext::Header => write!(f, "writing the schema header"),
+        }
+    }
+}
+
+/// An error returned by the [`Logfmt`](crate::Logfmt) drain.
+///
+/// It wraps the underlying I/O error (available via
+/// [`source`](std::error::Error::source)) with the
+/// [`ErrorContext`] of where logging the record failed.
+#[derive(Debug)]
+pub struct Error {
+    context: ErrorContext,
+    source: io::Error,
+}
+
+impl Error {
+    pub(crate) fn new(context: ErrorContext, source: impl Into<io::Error>) -> Self {
+        Error {
+            context,
+            source: source.into(),
+        }
+    }
+
+    /// What the drain was doing when the error happened.
+    pub fn context(&self) -> &ErrorContext {
+        &self.context
+    }
+
+    /// Returns the underlying I/O error.
+    pub fn into_io_error(self) -> io::Error {
+        self.source
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "logfmt: failed {}", self.context)
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(e.source.kind(), e)
+    }
+}
@@ -0,0 +1,42 @@
+//! Generating unique identifiers without depending on a UUID crate.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32 alphabet, as used by ULIDs.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Returns 64 random bits, seeded from the
+/// per-process random keys that std uses for `HashMap`s.
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+/// Generates a [ULID](https://github.com/ulid/spec): 48 bits of
+/// milliseconds since the unix epoch followed by 80 random bits,
+/// encoded as 26 characters of base32.
+///
+/// The randomness is good enough to tell records apart, but it is
+/// not cryptographically secure.
+pub(crate) fn ulid(now: SystemTime) -> String {
+    let millis = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis()) & ((1 << 48) - 1);
+    let random =
+        (u128::from(random_u64()) << 16 | u128::from(random_u64() & 0xffff)) & ((1 << 80) - 1);
+    encode(millis << 80 | random)
+}
+
+/// Encodes 128 bits as 26 base32 characters (the top two bits of
+/// the first character are always zero).
+pub(crate) fn encode(mut value: u128) -> String {
+    let mut out = [0u8; 26];
+    for slot in out.iter_mut().rev() {
+        *slot = ALPHABET[(value & 0x1f) as usize];
+        value >>= 5;
+    }
+    out.iter().map(|&b| b as char).collect()
+}
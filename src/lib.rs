@@ -20,202 +20,3343 @@
 //! DEBG | #tag	hi there	logger="tests" foo="bar\'baz\""
 //! ```
 //!
+//! # Features
+//! * `syslog`: the [`syslog`] module, which sends records to a syslog daemon.
+//! * `nested-values`: flattens nested values (logged via slog's
+//!   `emit_serde`) into fields with dotted keys, like `req.user.id=5`.
+//! * `cpu-time`: the [`with_cpu_time`](LogfmtBuilder::with_cpu_time)
+//!   option, which adds the process's CPU time to each record.
+//!
 
-use slog::{o, Error, Key, OwnedKVList, Record, Value, KV};
+use slog::{o, BytesKind, Key, Level, OwnedKVList, Record, Value, KV};
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Arguments;
-use std::io;
+use std::io::{self, IsTerminal};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub mod batch;
+#[cfg(feature = "cpu-time")]
+mod cpu;
+mod error;
+mod id;
+#[cfg(feature = "nested-values")]
+mod nested;
+pub mod ring;
+pub mod split;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+mod time;
+pub mod values;
+pub mod writer;
+
+pub use error::{Error, ErrorContext};
+pub use time::TimestampFormat;
 
 /// A decision on whether to print a key/value pair.
+#[derive(Clone, Copy)]
 pub enum Redaction {
     /// Print the value as-is.
     Plain,
 
-    /// Do not print the entry at all.
-    Skip,
+    /// Do not print the entry at all.
+    Skip,
+
+    /// Redact the value with the given function.
+    Redact(fn(&'_ dyn Value) -> Arguments),
+
+    /// Print the key, but replace its value with a constant marker,
+    /// like `<redacted>`.
+    ///
+    /// Unlike [`Skip`](Redaction::Skip), this keeps the fact that the
+    /// field was present visible in the output.
+    Mark(&'static str),
+
+    /// Print the key with only the length of the value in bytes, like
+    /// `body="<123 bytes>"`, e.g. to keep track of payload sizes
+    /// without logging their content.
+    LengthOnly,
+}
+
+/// How to render bytes that aren't valid UTF-8 in a
+/// [`BytesValue`](values::BytesValue).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUtf8Policy {
+    /// Replace each invalid sequence with U+FFFD REPLACEMENT CHARACTER.
+    Replace,
+
+    /// Write invalid bytes as `\xNN` escapes.
+    Escape,
+
+    /// Leave invalid bytes out.
+    Skip,
+
+    /// Fail to log the record.
+    Strict,
+}
+
+impl InvalidUtf8Policy {
+    /// Turns `bytes` into text according to this policy, failing with
+    /// [`io::ErrorKind::InvalidData`] under [`Strict`](InvalidUtf8Policy::Strict).
+    ///
+    /// Record messages are always valid UTF-8, so this only matters
+    /// for bytes. It is public so that a
+    /// [custom prefix](LogfmtBuilder::set_prefix) writing a
+    /// [`BytesValue`](values::BytesValue) in the message position can
+    /// treat it the same way as a field:
+    ///
+    /// ```rust
+    /// # use slog::Record;
+    /// # use slog_logfmt::InvalidUtf8Policy;
+    /// fn prefix(io: &mut dyn std::io::Write, rec: &Record) -> slog::Result {
+    ///     let payload: &[u8] = b"caf\xe9";
+    ///     let text = InvalidUtf8Policy::Escape.decode(payload)?;
+    ///     write!(io, "{} | {}\t", rec.level().as_short_str(), text)?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn decode(self, bytes: &[u8]) -> io::Result<Cow<'_, str>> {
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return Ok(text.into());
+        }
+        match self {
+            InvalidUtf8Policy::Strict => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bytes are not valid UTF-8",
+            )),
+            _ => Ok(self.render(bytes).into()),
+        }
+    }
+
+    fn render(self, bytes: &[u8]) -> String {
+        let mut out = String::with_capacity(bytes.len());
+        for chunk in bytes.utf8_chunks() {
+            out.push_str(chunk.valid());
+            let invalid = chunk.invalid();
+            match self {
+                InvalidUtf8Policy::Replace if !invalid.is_empty() => out.push('\u{FFFD}'),
+                InvalidUtf8Policy::Escape => {
+                    for byte in invalid {
+                        out.push_str(&format!("\\x{:02x}", byte));
+                    }
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+}
+
+/// What [`Logfmt::log_panic`] needs to know about a panic.
+///
+/// This is implemented for the [`PanicHookInfo`](panic::PanicHookInfo)
+/// that a panic hook receives.
+pub trait PanicDetails {
+    /// The panic message, if the payload was a string.
+    fn message(&self) -> Option<&str>;
+
+    /// The file, line and column the panic happened at.
+    fn location(&self) -> Option<(&str, u32, u32)>;
+}
+
+impl PanicDetails for panic::PanicHookInfo<'_> {
+    fn message(&self) -> Option<&str> {
+        let payload = self.payload();
+        payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    }
+
+    fn location(&self) -> Option<(&str, u32, u32)> {
+        panic::PanicHookInfo::location(self).map(|l| (l.file(), l.line(), l.column()))
+    }
+}
+
+/// How log levels are mapped to numbers for the `level_num` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelNumbering {
+    /// Syslog severities: `critical=2` through `debug=7`; trace is
+    /// also logged as `7`.
+    Syslog,
+
+    /// slog's own numbering, from `critical=1` through `trace=6`.
+    Slog,
+}
+
+impl LevelNumbering {
+    /// Returns the number for `level` in this scheme.
+    pub fn number(self, level: Level) -> usize {
+        match self {
+            LevelNumbering::Syslog => match level {
+                Level::Critical => 2,
+                Level::Error => 3,
+                Level::Warning => 4,
+                Level::Info => 6,
+                Level::Debug | Level::Trace => 7,
+            },
+            LevelNumbering::Slog => level.as_usize(),
+        }
+    }
+}
+
+/// How levels are labelled in the prefix and the `level` field.
+#[derive(Debug, Clone, Copy)]
+pub enum LevelStyle {
+    /// Four-letter labels like `INFO` and `ERRO`.
+    Short,
+
+    /// Full names like `INFO` and `ERROR`.
+    Full,
+
+    /// Labels chosen by a function, e.g. to use emoji.
+    Custom(fn(Level) -> &'static str),
+}
+
+impl LevelStyle {
+    fn label(self, level: Level) -> &'static str {
+        match self {
+            LevelStyle::Short => level.as_short_str(),
+            LevelStyle::Full => level.as_str(),
+            LevelStyle::Custom(label) => label(level),
+        }
+    }
+}
+
+/// How the default prefix marks a record's tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagMarker {
+    /// `#tag`.
+    Hash,
+
+    /// `[tag]`.
+    Brackets,
+
+    /// The tag between the given prefix and suffix, like `@tag` for
+    /// `Custom("@", "")`. Two empty strings write the bare tag.
+    Custom(&'static str, &'static str),
+}
+
+impl TagMarker {
+    fn parts(self) -> (&'static str, &'static str) {
+        match self {
+            TagMarker::Hash => ("#", ""),
+            TagMarker::Brackets => ("[", "]"),
+            TagMarker::Custom(prefix, suffix) => (prefix, suffix),
+        }
+    }
+}
+
+/// What to do with fields whose key is empty, which would be written
+/// as invalid logfmt like `=value`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyKeyPolicy {
+    /// Leave the field out.
+    Skip,
+
+    /// Write the field with this key instead, like `_=value`.
+    Placeholder(&'static str),
+
+    /// Fail to log the record.
+    Error,
+}
+
+/// The kinds of values that an
+/// [`assignment_style`](LogfmtBuilder::assignment_style) can tell
+/// apart, by the `emit_*` method they were logged with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeClass {
+    /// Integers and floating point numbers.
+    Number,
+
+    /// Booleans.
+    Bool,
+
+    /// Strings, characters, and values formatted via `Display` or
+    /// `Debug`.
+    String,
+
+    /// Everything else, like `None`, byte strings, errors and nested
+    /// values.
+    Other,
+}
+
+impl TypeClass {
+    /// Classifies the type name of a field.
+    fn of(ty: &str) -> TypeClass {
+        match ty {
+            "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "u128" | "i128"
+            | "usize" | "isize" | "f32" | "f64" => TypeClass::Number,
+            "bool" => TypeClass::Bool,
+            "str" | "char" | "fmt" => TypeClass::String,
+            _ => TypeClass::Other,
+        }
+    }
+}
+
+/// How boolean values are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoolStyle {
+    /// As `key=true` or `key=false`.
+    Value,
+
+    /// Like command line flags: `true` values are written as the bare
+    /// `key`, and `false` values are left out.
+    FlagPresence,
+}
+
+/// How errors (logged via slog's `emit_error`) are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorStyle {
+    /// Only the error's own message.
+    Display,
+
+    /// The messages of the error and all its sources, joined with
+    /// `: `, like `request failed: timeout`.
+    DisplayChain,
+
+    /// The error's `Debug` representation.
+    Debug,
+}
+
+/// What to do with a record, regardless of the
+/// [`sample_rate`](LogfmtBuilder::sample_rate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleDecision {
+    /// Always log the record.
+    Force,
+
+    /// Never log the record.
+    Drop,
+
+    /// Leave it to the sample rate.
+    Default,
+}
+
+/// What goes at the start of each line, before the logfmt fields.
+#[derive(Clone, Copy)]
+enum Prefix {
+    /// The human-readable prefix, see [`Logfmt`].
+    Default,
+
+    /// A user-supplied prefix function.
+    Custom(fn(&mut dyn io::Write, &Record) -> slog::Result),
+}
+
+/// The sort weight of keys that aren't listed in
+/// [`key_priority`](LogfmtBuilder::key_priority).
+pub const DEFAULT_KEY_PRIORITY: i32 = 100;
+
+type KeyLevelGate = dyn Fn(&Key, Level) -> bool + Send + Sync;
+type SampleOverride = dyn Fn(&Record) -> SampleDecision + Send + Sync;
+type ExtraFields = dyn Fn(&Record, &mut dyn slog::Serializer) -> slog::Result + Send + Sync;
+type AfterRecord = dyn Fn(&Record) + Send + Sync;
+type QuotePredicate = dyn Fn(&str, &str) -> bool + Send + Sync;
+type AssignmentStyle = dyn Fn(TypeClass) -> &'static str + Send + Sync;
+type ValueRedactor = dyn Fn(&str) -> Option<Cow<'_, str>> + Send + Sync;
+type DeriveField = dyn Fn(&FieldMap) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+struct Options {
+    prefix: Prefix,
+    prefix_msg: bool,
+    quote_prefix_msg: bool,
+    prefix_only_tagged: bool,
+    prefix_dedup: bool,
+    collapse_msg_whitespace: bool,
+    escape_prefix_separator: bool,
+    timestamp: Option<TimestampFormat>,
+    clock: Option<fn() -> SystemTime>,
+    leading_level: bool,
+    print_level: bool,
+    level_num: Option<LevelNumbering>,
+    print_msg: bool,
+    print_tag: bool,
+    default_tag: Option<String>,
+    tag_marker: TagMarker,
+    force_quotes: bool,
+    force_quotes_when: Option<Arc<QuotePredicate>>,
+    field_separator: String,
+    meta_separator: Option<String>,
+    collapse_crlf: bool,
+    char_replacements: HashMap<char, String>,
+    quote_numeric_strings: bool,
+    minimal_quoting: bool,
+    escape_forward_slash: bool,
+    bool_style: BoolStyle,
+    empty_key: EmptyKeyPolicy,
+    level_style: LevelStyle,
+    level_only_non_info: bool,
+    color: bool,
+    error_style: ErrorStyle,
+    float_precision: Option<usize>,
+    float_integer_shortcut: bool,
+    columns: Vec<(String, usize)>,
+    vertical: bool,
+    right_align_numbers: bool,
+    prefix_fields: Vec<String>,
+    tsv: Option<Vec<String>>,
+    key_priority: HashMap<String, i32>,
+    natural_sort: bool,
+    max_key_len: Option<usize>,
+    rename_keys: HashMap<String, String>,
+    key_escape: EscapeStyle,
+    value_escape: EscapeStyle,
+    type_suffix: bool,
+    assignment_style: Option<Arc<AssignmentStyle>>,
+    nest_separator: String,
+    tag_as_key_prefix: bool,
+    #[cfg_attr(not(feature = "nested-values"), allow(dead_code))]
+    max_nest_depth: Option<usize>,
+    #[cfg_attr(not(feature = "nested-values"), allow(dead_code))]
+    skip_empty_nested: bool,
+    catch_value_panics: bool,
+    errors_into_msg: bool,
+    redactor: Option<fn(&&str) -> Redaction>,
+    cache_redaction: bool,
+    redaction_cache: Arc<Mutex<HashMap<String, Redaction>>>,
+    value_redactor: Option<Arc<ValueRedactor>>,
+    level_gate: Option<Arc<KeyLevelGate>>,
+    error_fields: Option<(Level, Arc<ExtraFields>)>,
+    derived_fields: Vec<(String, Arc<DeriveField>)>,
+    default_fields: Vec<(String, String)>,
+    value_budget: Option<usize>,
+    strict_field_limit: Option<(usize, bool)>,
+    invalid_utf8: InvalidUtf8Policy,
+    report_field_count: bool,
+    list_redacted_keys: bool,
+    with_delta: bool,
+    line_numbers: bool,
+    delta_keys: Vec<String>,
+    #[cfg_attr(not(feature = "cpu-time"), allow(dead_code))]
+    with_cpu_time: bool,
+    queue_depth: Option<Arc<AtomicUsize>>,
+    sample_rate: Option<u64>,
+    rate_limit: Option<u32>,
+    rate_limit_exempt: Option<Level>,
+    summary_every: Option<usize>,
+    sample_override: Option<Arc<SampleOverride>>,
+    after_record: Option<Arc<AfterRecord>>,
+    auto_correlation_id: bool,
+    logger_path_key: Option<String>,
+    max_context_fields: Option<usize>,
+    build_info: Option<String>,
+    with_instance_id: bool,
+    instance_id: Option<String>,
+    with_pid: bool,
+    pid: Option<u32>,
+    with_hostname: bool,
+    hostname: Option<String>,
+    with_seq: bool,
+    with_elapsed: bool,
+    correlation_id_key: String,
+    schema_header: bool,
+    deterministic: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            prefix: Prefix::Default,
+            prefix_msg: true,
+            quote_prefix_msg: false,
+            prefix_only_tagged: false,
+            prefix_dedup: false,
+            collapse_msg_whitespace: false,
+            escape_prefix_separator: false,
+            timestamp: None,
+            clock: None,
+            leading_level: false,
+            print_level: false,
+            level_num: None,
+            print_msg: false,
+            print_tag: false,
+            default_tag: None,
+            tag_marker: TagMarker::Hash,
+            force_quotes: false,
+            force_quotes_when: None,
+            field_separator: " ".to_string(),
+            meta_separator: None,
+            collapse_crlf: false,
+            char_replacements: HashMap::new(),
+            quote_numeric_strings: false,
+            minimal_quoting: false,
+            escape_forward_slash: false,
+            bool_style: BoolStyle::Value,
+            empty_key: EmptyKeyPolicy::Skip,
+            level_style: LevelStyle::Short,
+            level_only_non_info: false,
+            color: false,
+            error_style: ErrorStyle::DisplayChain,
+            float_precision: None,
+            float_integer_shortcut: false,
+            columns: Vec::new(),
+            vertical: false,
+            right_align_numbers: false,
+            prefix_fields: Vec::new(),
+            tsv: None,
+            key_priority: HashMap::new(),
+            natural_sort: false,
+            max_key_len: None,
+            rename_keys: HashMap::new(),
+            key_escape: EscapeStyle::Raw,
+            value_escape: EscapeStyle::Quote,
+            type_suffix: false,
+            assignment_style: None,
+            nest_separator: ".".to_string(),
+            tag_as_key_prefix: false,
+            max_nest_depth: None,
+            skip_empty_nested: false,
+            catch_value_panics: false,
+            errors_into_msg: false,
+            redactor: None,
+            cache_redaction: false,
+            redaction_cache: Default::default(),
+            value_redactor: None,
+            level_gate: None,
+            error_fields: None,
+            derived_fields: Vec::new(),
+            default_fields: Vec::new(),
+            value_budget: None,
+            strict_field_limit: None,
+            invalid_utf8: InvalidUtf8Policy::Replace,
+            report_field_count: false,
+            list_redacted_keys: false,
+            with_delta: false,
+            line_numbers: false,
+            delta_keys: Vec::new(),
+            with_cpu_time: false,
+            queue_depth: None,
+            sample_rate: None,
+            rate_limit: None,
+            rate_limit_exempt: None,
+            summary_every: None,
+            sample_override: None,
+            after_record: None,
+            auto_correlation_id: false,
+            logger_path_key: None,
+            max_context_fields: None,
+            build_info: None,
+            with_instance_id: false,
+            instance_id: None,
+            with_pid: false,
+            pid: None,
+            with_hostname: false,
+            hostname: None,
+            with_seq: false,
+            with_elapsed: false,
+            correlation_id_key: "trace_id".to_string(),
+            schema_header: false,
+            deterministic: false,
+        }
+    }
+}
+
+/// A drain & formatter for [logfmt](https://brandur.org/logfmt)-formatted messages.
+///
+/// # Format
+/// The default format looks like the somewhat-more-human-readable
+/// format in https://brandur.org/logfmt#human. You can customize it
+/// with the [`LogfmtBuilder`] method `set_prefix`.
+///
+/// # Output
+/// Everything is written to the underlying [`io::Write`] as bytes,
+/// and the drain never reads back or re-encodes what it wrote. Bytes
+/// that a custom prefix writes end up in the output verbatim, even if
+/// they aren't valid UTF-8; field values are always valid UTF-8.
+pub struct Logfmt<W: io::Write> {
+    io: RefCell<W>,
+    high: Option<(Level, RefCell<W>)>,
+    options: Options,
+    state: State,
+}
+
+/// Bookkeeping that the drain updates as it logs records.
+#[derive(Default)]
+struct State {
+    last_record: Mutex<Option<Instant>>,
+    indent: AtomicUsize,
+    paused: AtomicBool,
+    sampled: AtomicU64,
+    summary: Summary,
+    rate_limiter: RateLimiter,
+    last_prefix: Mutex<Vec<u8>>,
+    last_values: Mutex<HashMap<String, f64>>,
+    header_written: AtomicBool,
+    lines: AtomicU64,
+    high_lines: AtomicU64,
+    seq: AtomicU64,
+    built: Option<Instant>,
+}
+
+/// Level tallies since the last summary record.
+#[derive(Default)]
+struct Summary {
+    total: AtomicUsize,
+    errors: AtomicUsize,
+    warnings: AtomicUsize,
+}
+
+impl Summary {
+    /// Counts a record, returning the summary fields if it's time to
+    /// write them (and starting a new tally).
+    fn count(&self, level: Level, every: usize) -> Option<Vec<Field>> {
+        if level.is_at_least(Level::Error) {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        } else if level == Level::Warning {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.total.fetch_add(1, Ordering::Relaxed) + 1 < every {
+            return None;
+        }
+        let total = self.total.swap(0, Ordering::Relaxed);
+        let errors = self.errors.swap(0, Ordering::Relaxed);
+        let warnings = self.warnings.swap(0, Ordering::Relaxed);
+        Some(vec![
+            Field::new("_summary", "u8", "1"),
+            Field::new("total", "usize", total.to_string()),
+            Field::new("errors", "usize", errors.to_string()),
+            Field::new("warnings", "usize", warnings.to_string()),
+        ])
+    }
+}
+
+/// A token bucket holding up to one second's worth of records.
+#[derive(Default)]
+struct RateLimiter {
+    /// Whether the bucket was filled for the first record yet.
+    started: AtomicBool,
+    /// Available tokens, in millionths of a record.
+    tokens: AtomicU64,
+    /// When tokens were last added, in microseconds since the epoch.
+    refilled: AtomicU64,
+    /// Records dropped since the last one that was let through.
+    dropped: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Takes a token for a record, returning whether it may be logged.
+    fn admit(&self, per_second: u32, now: SystemTime) -> bool {
+        let per_second = u64::from(per_second);
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_micros() as u64);
+        let elapsed = now.saturating_sub(self.refilled.swap(now, Ordering::Relaxed));
+        let capacity = per_second * 1_000_000;
+        let tokens = if self.started.swap(true, Ordering::Relaxed) {
+            self.tokens
+                .load(Ordering::Relaxed)
+                .saturating_add(elapsed.saturating_mul(per_second))
+                .min(capacity)
+        } else {
+            capacity
+        };
+        if tokens < 1_000_000 {
+            self.tokens.store(tokens, Ordering::Relaxed);
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.tokens.store(tokens - 1_000_000, Ordering::Relaxed);
+        true
+    }
+
+    /// Returns how many records were dropped since the last call.
+    fn take_dropped(&self) -> u64 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl<W: io::Write> Logfmt<W> {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(io: W) -> LogfmtBuilder<W> {
+        LogfmtBuilder {
+            io,
+            high: None,
+            options: Default::default(),
+            indent: 0,
+        }
+    }
+
+    /// Changes how many spaces each line is indented by, starting
+    /// with the next record. See [`LogfmtBuilder::indent`].
+    pub fn set_indent(&self, indent: usize) {
+        self.state.indent.store(indent, Ordering::Relaxed);
+    }
+
+    /// The key of the field holding the level, when
+    /// [`print_level`](LogfmtBuilder::print_level) is on.
+    pub fn level_key(&self) -> &str {
+        self.options.renamed_key("level")
+    }
+
+    /// The key of the field holding the message, when
+    /// [`print_msg`](LogfmtBuilder::print_msg) is on.
+    pub fn msg_key(&self) -> &str {
+        self.options.renamed_key("msg")
+    }
+
+    /// The key of the field holding the timestamp, when
+    /// [`timestamp`](LogfmtBuilder::timestamp) is set.
+    pub fn ts_key(&self) -> &str {
+        self.options.renamed_key("ts")
+    }
+
+    /// The key that correlation IDs are looked up and generated under,
+    /// see [`correlation_id_key`](LogfmtBuilder::correlation_id_key).
+    pub fn correlation_id_key(&self) -> &str {
+        &self.options.correlation_id_key
+    }
+
+    /// The context key that logger paths are built from, or `None` if
+    /// [`track_logger_path`](LogfmtBuilder::track_logger_path) is off.
+    pub fn logger_path_key(&self) -> Option<&str> {
+        self.options.logger_path_key.as_deref()
+    }
+
+    /// Replaces the writer that records are written to, returning the
+    /// old one, e.g. to reopen a log file after it was rotated.
+    ///
+    /// The old writer is flushed before it is returned. Since the
+    /// drain isn't `Sync`, it must be behind a lock (like a `Mutex`)
+    /// or `slog-async` to be shared between threads, which also keeps
+    /// this from racing with records being logged. A second writer
+    /// set with [`split_writer`](LogfmtBuilder::split_writer) is left
+    /// as it is.
+    pub fn replace_writer(&self, io: W) -> W {
+        let mut old = std::mem::replace(&mut *self.io.borrow_mut(), io);
+        self.state.lines.store(0, Ordering::Relaxed);
+        let _ = old.flush();
+        old
+    }
+
+    /// Logs a panic as an error-level record, for use in a panic hook
+    /// where there is no logger to go through. The panic message
+    /// becomes the record's message and the place it happened at is
+    /// written as a `location` field in `file:line:column` form.
+    ///
+    /// ```rust
+    /// use slog_logfmt::Logfmt;
+    /// use std::io::stderr;
+    /// use std::sync::Mutex;
+    ///
+    /// let drain = Mutex::new(Logfmt::new(stderr()).build());
+    /// std::panic::set_hook(Box::new(move |info| {
+    ///     if let Ok(drain) = drain.lock() {
+    ///         let _ = drain.log_panic(info);
+    ///     }
+    /// }));
+    /// ```
+    pub fn log_panic<P: PanicDetails + ?Sized>(&self, info: &P) -> Result<(), Error> {
+        let message = info.message().unwrap_or("panicked");
+        match info.location() {
+            Some((file, line, column)) => {
+                let location = format!("{}:{}:{}", file, line, column);
+                self.log_panic_record(message, slog::b!("location" => location))
+            }
+            None => self.log_panic_record(message, slog::b!()),
+        }
+    }
+
+    fn log_panic_record(&self, message: &str, kv: slog::BorrowedKV) -> Result<(), Error> {
+        static LOCATION: slog::RecordLocation = slog::RecordLocation {
+            file: "",
+            line: 0,
+            column: 0,
+            function: "",
+            module: "",
+        };
+        static STATIC: slog::RecordStatic<'static> = slog::RecordStatic {
+            location: &LOCATION,
+            level: Level::Error,
+            tag: "",
+        };
+        slog::Drain::log(
+            self,
+            &Record::new(&STATIC, &format_args!("{}", message), kv),
+            &OwnedKVList::from(o!()),
+        )
+    }
+
+    /// Formats the [`schema_header`](LogfmtBuilder::schema_header)
+    /// line, without the newline.
+    fn schema_header(&self) -> String {
+        let options = &self.options;
+        let fields = match &options.tsv {
+            Some(columns) => columns.clone(),
+            None => {
+                let mut fields: Vec<String> = Vec::new();
+                if options.timestamp.is_some() {
+                    fields.push(self.ts_key().to_string());
+                }
+                if options.print_level {
+                    fields.push(self.level_key().to_string());
+                }
+                if options.level_num.is_some() {
+                    fields.push("level_num".to_string());
+                }
+                if options.print_msg {
+                    fields.push(self.msg_key().to_string());
+                }
+                if options.print_tag {
+                    fields.push("tag".to_string());
+                }
+                let mut prioritized: Vec<&String> = options.key_priority.keys().collect();
+                prioritized.sort();
+                let named = options
+                    .columns
+                    .iter()
+                    .map(|(key, _)| key)
+                    .chain(prioritized);
+                for key in named {
+                    if !fields.contains(key) {
+                        fields.push(key.clone());
+                    }
+                }
+                let priority = &options.key_priority;
+                fields
+                    .sort_by_key(|key| priority.get(key).copied().unwrap_or(DEFAULT_KEY_PRIORITY));
+                fields
+            }
+        };
+
+        let mut out = String::from("{\"format\":\"logfmt\",\"keys\":{");
+        let keys = [
+            ("level", self.level_key()),
+            ("msg", self.msg_key()),
+            ("ts", self.ts_key()),
+        ];
+        for (i, (name, key)) in keys.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_json_string(&mut out, name);
+            out.push(':');
+            write_json_string(&mut out, key);
+        }
+        out.push_str("},\"fields\":[");
+        for (i, key) in fields.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write_json_string(&mut out, key);
+        }
+        out.push_str("],\"levels\":[");
+        for (i, level) in (1..=6).filter_map(Level::from_usize).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str("{\"name\":");
+            write_json_string(&mut out, &level.as_str().to_lowercase());
+            out.push_str(",\"label\":");
+            write_json_string(&mut out, options.level_style.label(level));
+            if let Some(numbering) = options.level_num {
+                out.push_str(&format!(",\"num\":{}", numbering.number(level)));
+            }
+            out.push('}');
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// Pauses or resumes output. While paused, records are dropped
+    /// without being formatted.
+    pub fn set_paused(&self, paused: bool) {
+        self.state.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Writes a record's indentation, prefix and fields, but not the
+    /// newline after them.
+    fn write_line(
+        &self,
+        io: &mut W,
+        indent: usize,
+        record: &Record,
+        msg: Option<&str>,
+        fields: &[Field],
+    ) -> Result<(), Error> {
+        write!(io, "{:indent$}", "", indent = indent)
+            .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+        let prefixed = !self.options.prefix_only_tagged || !self.options.tag(record).is_empty();
+        if self.options.vertical {
+            return self.write_vertical(io, indent, prefixed, record, msg, fields);
+        }
+        if prefixed {
+            if self.options.prefix_dedup {
+                let mut prefix = Vec::new();
+                self.options
+                    .write_prefix(&mut prefix, record, msg, fields)
+                    .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+                let mut last = self.state.last_prefix.lock().unwrap();
+                if *last != prefix {
+                    io.write_all(&prefix)
+                        .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+                    *last = prefix;
+                }
+            } else {
+                self.options
+                    .write_prefix(io, record, msg, fields)
+                    .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+            }
+        }
+        self.options.write_fields(io, fields)?;
+        Ok(())
+    }
+
+    /// Writes the prefix as a header line, followed by one indented
+    /// line per field.
+    fn write_vertical(
+        &self,
+        io: &mut W,
+        indent: usize,
+        prefixed: bool,
+        record: &Record,
+        msg: Option<&str>,
+        fields: &[Field],
+    ) -> Result<(), Error> {
+        let mut prefix = Vec::new();
+        if prefixed {
+            self.options
+                .write_prefix(&mut prefix, record, msg, fields)
+                .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+        }
+        while prefix.last() == Some(&b'\t') {
+            prefix.pop();
+        }
+        io.write_all(&prefix)
+            .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+        for field in fields {
+            write!(io, "\n{:indent$}", "", indent = indent + 2)
+                .and_then(|_| self.options.write_field(io, field, true))
+                .map_err(|e| Error::new(ErrorContext::Field(field.key.clone()), e))?;
+        }
+        Ok(())
+    }
+
+    /// Adds a `<key>_delta` field after each of the `delta_keys`
+    /// fields with a numeric value, holding the difference to the
+    /// value it had in the previous record.
+    fn add_value_deltas(&self, fields: &mut Vec<Field>) {
+        let mut last_values = self.state.last_values.lock().unwrap();
+        let mut i = 0;
+        while i < fields.len() {
+            let field = &fields[i];
+            i += 1;
+            if !self.options.delta_keys.contains(&field.key) {
+                continue;
+            }
+            let value = match field.value.parse::<f64>() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            if let Some(last) = last_values.insert(field.key.clone(), value) {
+                let key = format!("{}_delta", field.key);
+                let delta = value - last;
+                let field = if delta.fract() == 0.0 && delta.abs() < i64::MAX as f64 {
+                    Field::new(key, "i64", (delta as i64).to_string())
+                } else {
+                    Field::new(key, "f64", delta.to_string())
+                };
+                fields.insert(i, field);
+                i += 1;
+            }
+        }
+    }
+
+    /// Decides whether to log `record`, returning the rate it was
+    /// sampled at (1 if it was forced), or `None` to drop it.
+    fn sample(&self, record: &Record) -> Option<u64> {
+        let decision = self
+            .options
+            .sample_override
+            .as_ref()
+            .map_or(SampleDecision::Default, |decide| decide(record));
+        match (decision, self.options.sample_rate) {
+            (SampleDecision::Drop, _) => None,
+            (SampleDecision::Force, _) | (SampleDecision::Default, None) => Some(1),
+            (SampleDecision::Default, Some(rate)) => self
+                .state
+                .sampled
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(rate)
+                .then_some(rate),
+        }
+    }
+
+    /// Returns the milliseconds since the previous call, and 0 on the
+    /// first one.
+    fn delta_ms(&self) -> u128 {
+        let now = Instant::now();
+        let mut last = self.state.last_record.lock().unwrap();
+        let delta = last.map_or(0, |last| now.saturating_duration_since(last).as_millis());
+        *last = Some(now);
+        if self.options.deterministic {
+            0
+        } else {
+            delta
+        }
+    }
+
+    /// Builds a drain that writes each record twice: in the
+    /// human-readable default format to `terminal`, and as strict
+    /// logfmt to `file`, with `ts`, `level` and `msg` fields instead
+    /// of a prefix. With `color`, the terminal output is
+    /// [colored](LogfmtBuilder::color); the file output never is.
+    ///
+    /// See [`Logfmt::dev_and_file`] for the common case of showing
+    /// records on stderr. Since the drain isn't `Sync`, you'll want
+    /// to wrap it in `slog-async` or a `Mutex`.
+    pub fn dev_and_file_with<F: io::Write>(
+        terminal: W,
+        color: bool,
+        file: F,
+    ) -> slog::Duplicate<Logfmt<W>, Logfmt<F>> {
+        let human = Logfmt::new(terminal).color(color).build();
+        let strict = Logfmt::new(file)
+            .no_prefix()
+            .timestamp(TimestampFormat::Rfc3339Millis)
+            .print_level(true)
+            .print_msg(true)
+            .build();
+        slog::Duplicate::new(human, strict)
+    }
+
+    /// Returns a serializer that writes logfmt fields to `io`,
+    /// formatted according to this drain's options.
+    pub fn serializer<'a, V: io::Write>(&'a self, io: &'a mut V) -> LogfmtSerializer<'a, V> {
+        LogfmtSerializer {
+            io,
+            options: &self.options,
+            first: true,
+            scratch: Vec::new(),
+        }
+    }
+}
+
+impl Logfmt<writer::Stdio> {
+    /// Starts building a drain that writes records at `threshold` or
+    /// a more severe level to stderr, and everything else to stdout.
+    pub fn split_stderr(threshold: Level) -> LogfmtBuilder<writer::Stdio> {
+        Logfmt::new(writer::Stdio::Stdout(io::stdout()))
+            .split_writer(threshold, writer::Stdio::Stderr(io::stderr()))
+    }
+
+    /// Builds a drain for local development that shows records on
+    /// stderr in the human-readable format (colored if stderr is a
+    /// terminal), and also writes them to `file` as strict logfmt,
+    /// see [`Logfmt::dev_and_file_with`].
+    ///
+    /// ```rust,no_run
+    /// use slog::{o, Drain, Logger};
+    /// use slog_logfmt::Logfmt;
+    /// use std::fs::File;
+    ///
+    /// let file = File::create("app.log").unwrap();
+    /// let drain = Logfmt::dev_and_file(file).fuse();
+    /// let drain = slog_async::Async::new(drain).build().fuse();
+    /// let logger = Logger::root(drain, o!());
+    /// ```
+    pub fn dev_and_file<F: io::Write>(
+        file: F,
+    ) -> slog::Duplicate<Logfmt<writer::Stdio>, Logfmt<F>> {
+        let color = io::stderr().is_terminal();
+        Logfmt::dev_and_file_with(writer::Stdio::Stderr(io::stderr()), color, file)
+    }
+}
+
+/// A constructor for a [`Logfmt`] drain.
+pub struct LogfmtBuilder<W: io::Write> {
+    io: W,
+    high: Option<(Level, W)>,
+    options: Options,
+    indent: usize,
+}
+
+impl<W: io::Write> LogfmtBuilder<W> {
+    /// Constructs the drain.
+    pub fn build(self) -> Logfmt<W> {
+        let state = State {
+            built: Some(Instant::now()),
+            ..State::default()
+        };
+        state.indent.store(self.indent, Ordering::Relaxed);
+        let mut options = self.options;
+        if options.with_instance_id && options.instance_id.is_none() {
+            options.instance_id = Some(id::ulid(SystemTime::now()));
+        }
+        if options.with_pid && options.pid.is_none() {
+            options.pid = Some(std::process::id());
+        }
+        if options.with_hostname && options.hostname.is_none() {
+            options.hostname = hostname();
+        }
+        Logfmt {
+            io: RefCell::new(self.io),
+            high: self
+                .high
+                .map(|(threshold, high)| (threshold, RefCell::new(high))),
+            options,
+            state,
+        }
+    }
+
+    /// Write records at `threshold` or a more severe level to `high`
+    /// instead of the drain's main writer.
+    ///
+    /// See [`Logfmt::split_stderr`] for the common case of sending
+    /// warnings and errors to stderr.
+    pub fn split_writer(mut self, threshold: Level, high: W) -> Self {
+        self.high = Some((threshold, high));
+        self
+    }
+
+    /// Takes over the options that `other` changed from their
+    /// defaults, keeping this builder's writer.
+    ///
+    /// This allows layering configuration, like a base policy with
+    /// per-module overrides. Where both builders set an option,
+    /// `other` wins; options that `other` leaves at their default
+    /// are kept as they are here.
+    ///
+    /// ```rust
+    /// # use slog_logfmt::Logfmt;
+    /// let base = Logfmt::new(std::io::sink()).force_quotes();
+    /// let drain = Logfmt::new(std::io::stdout())
+    ///     .merge(&base)
+    ///     .print_level(true)
+    ///     .build();
+    /// ```
+    pub fn merge<V: io::Write>(mut self, other: &LogfmtBuilder<V>) -> Self {
+        self.options.merge(&other.options);
+        if other.indent != 0 {
+            self.indent = other.indent;
+        }
+        self
+    }
+
+    /// Flush the writer every `interval` from a background thread,
+    /// rather than after every record.
+    ///
+    /// This is useful for buffered or compressing writers, so their
+    /// output reaches its destination regularly without paying for a
+    /// flush per record. The thread is stopped, and the writer flushed
+    /// one last time, when the drain is dropped.
+    pub fn flush_interval(self, interval: Duration) -> LogfmtBuilder<writer::IntervalFlush<W>>
+    where
+        W: Send + 'static,
+    {
+        LogfmtBuilder {
+            io: writer::IntervalFlush::new(self.io, interval),
+            high: self
+                .high
+                .map(|(threshold, high)| (threshold, writer::IntervalFlush::new(high, interval))),
+            options: self.options,
+            indent: self.indent,
+        }
+    }
+
+    /// Set a function that prints a (not necessarily
+    /// logfmt-formatted) prefix to the output stream.
+    pub fn set_prefix(mut self, prefix: fn(&mut dyn io::Write, &Record) -> slog::Result) -> Self {
+        self.options.prefix = Prefix::Custom(prefix);
+        self
+    }
+
+    /// Sets the logger up to print no prefix, effectively starting the line entirely
+    /// logfmt field-formatted.
+    pub fn no_prefix(mut self) -> Self {
+        self.options.prefix = Prefix::Custom(|_, _| Ok(()));
+        self
+    }
+
+    /// Choose whether the default prefix includes the log message.
+    ///
+    /// This is independent of [`print_msg`](LogfmtBuilder::print_msg),
+    /// so the message can appear in the prefix, as a `msg` field,
+    /// both or neither. The default is to print it in the prefix.
+    pub fn prefix_msg(mut self, print: bool) -> Self {
+        self.options.prefix_msg = print;
+        self
+    }
+
+    /// Choose whether to collapse runs of whitespace (including tabs
+    /// and newlines) in the default prefix's message into single
+    /// spaces. The `msg` field is unaffected.
+    pub fn collapse_msg_whitespace(mut self, collapse: bool) -> Self {
+        self.options.collapse_msg_whitespace = collapse;
+        self
+    }
+
+    /// Choose whether to leave out prefixes that are the same as the
+    /// previous record's.
+    ///
+    /// The prefix is written on the first record and whenever it
+    /// changes, so in a run of records with the same level (and tag)
+    /// only the first line has it. Since the default prefix
+    /// includes the message, this is most useful together with
+    /// [`prefix_msg(false)`](LogfmtBuilder::prefix_msg).
+    pub fn prefix_dedup(mut self, dedup: bool) -> Self {
+        self.options.prefix_dedup = dedup;
+        self
+    }
+
+    /// Choose whether to escape the default prefix's ` | ` separator
+    /// where it appears in a record's tag or message, as ` \| `.
+    ///
+    /// This keeps the level's separator the first ` | ` on every
+    /// line, so the prefix can be split off unambiguously.
+    pub fn escape_prefix_separator(mut self, escape: bool) -> Self {
+        self.options.escape_prefix_separator = escape;
+        self
+    }
+
+    /// Choose whether to put the message in the default prefix in
+    /// quotes, escaping any quotes inside it like field values, e.g.
+    /// `INFO | "disk almost full"`. This makes it easy to tell where
+    /// a message with spaces ends.
+    pub fn quote_prefix_msg(mut self, quote: bool) -> Self {
+        self.options.quote_prefix_msg = quote;
+        self
+    }
+
+    /// Write the fields with the given keys into the default prefix,
+    /// in this order and between the tag and the message, like
+    /// `INFO | request_id=abc12<TAB>starting`. The fields are still
+    /// written in the field section, too.
+    pub fn prefix_fields(mut self, keys: &[&str]) -> Self {
+        self.options.prefix_fields = keys.iter().map(|key| key.to_string()).collect();
+        self
+    }
+
+    /// Choose whether to write the prefix only for records that have
+    /// a tag.
+    ///
+    /// Untagged records then start directly with their fields, so
+    /// you'll likely want [`print_msg`](LogfmtBuilder::print_msg) to
+    /// keep their message. The default is to prefix every record.
+    pub fn prefix_only_tagged(mut self, only_tagged: bool) -> Self {
+        self.options.prefix_only_tagged = only_tagged;
+        self
+    }
+
+    /// Sets a function that makes decisions on whether to log a field.
+    ///
+    /// This function must return a [`Redaction`] result, for example
+    /// `Redaction::Skip` to not log the field, `Redaction::Mark` to
+    /// log only a placeholder value, and `Redaction::Plain` to log
+    /// the field value in plain text.
+    ///
+    /// With the `nested-values` feature, it is also called with the
+    /// keys of maps inside nested values, which are then redacted
+    /// along with everything below them. Those keys aren't `'static`,
+    /// so the function takes a `&&str` rather than slog's `&Key`;
+    /// closures like `|&key| match key { .. }` work with either.
+    pub fn redact(mut self, redact: fn(&&str) -> Redaction) -> Self {
+        self.options.redactor = Some(redact);
+        self
+    }
+
+    /// Choose whether to remember the [redactor's](LogfmtBuilder::redact)
+    /// decision for each key, so that it runs only once per distinct
+    /// key rather than for every field logged. Off by default.
+    ///
+    /// Only turn this on if the redactor's decision depends on nothing
+    /// but the key.
+    pub fn cache_redaction(mut self, cache: bool) -> Self {
+        self.options.cache_redaction = cache;
+        self
+    }
+
+    /// Sets a function that redacts values by their content, no
+    /// matter their key, e.g. anything that looks like an email
+    /// address.
+    ///
+    /// It is called with each rendered value that the
+    /// [redactor](LogfmtBuilder::redact) left in plain text, and
+    /// returning `Some` replaces the value.
+    pub fn redact_values<F>(mut self, redact: F) -> Self
+    where
+        F: Fn(&str) -> Option<Cow<'_, str>> + Send + Sync + 'static,
+    {
+        self.options.value_redactor = Some(Arc::new(redact));
+        self
+    }
+
+    /// Sets a function that decides, based on the record's level,
+    /// whether a field should be logged at all.
+    ///
+    /// Fields for which the function returns `false` are skipped
+    /// before the redactor gets to see them. This is useful to keep
+    /// detail fields out of records above a certain level:
+    ///
+    /// ```rust
+    /// # use slog::Level;
+    /// # use slog_logfmt::Logfmt;
+    /// let drain = Logfmt::new(std::io::stdout())
+    ///     .level_gated_keys(|key, level| *key != "debug_detail" || !level.is_at_least(Level::Info))
+    ///     .build();
+    /// ```
+    pub fn level_gated_keys<F>(mut self, gate: F) -> Self
+    where
+        F: Fn(&Key, Level) -> bool + Send + Sync + 'static,
+    {
+        self.options.level_gate = Some(Arc::new(gate));
+        self
+    }
+
+    /// Sets a function that adds fields to records at `threshold` or
+    /// a more severe level, like an `alert=true` on errors.
+    ///
+    /// The fields are added after the record's own, and are subject
+    /// to redaction like any other field.
+    ///
+    /// ```rust
+    /// # use slog::Level;
+    /// # use slog_logfmt::Logfmt;
+    /// let drain = Logfmt::new(std::io::stdout())
+    ///     .error_fields(Level::Error, |_record, serializer| {
+    ///         serializer.emit_bool("alert", true)
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn error_fields<F>(mut self, threshold: Level, fields: F) -> Self
+    where
+        F: Fn(&Record, &mut dyn slog::Serializer) -> slog::Result + Send + Sync + 'static,
+    {
+        self.options.error_fields = Some((threshold, Arc::new(fields)));
+        self
+    }
+
+    /// Choose whether to print the log message.
+    ///
+    /// The default prefix already prints it, so the default is to skip.
+    pub fn print_msg(mut self, print: bool) -> Self {
+        self.options.print_msg = print;
+        self
+    }
+
+    /// Add a `ts` field holding the time the record was logged, as
+    /// the first field of each record.
+    pub fn timestamp(mut self, format: TimestampFormat) -> Self {
+        self.options.timestamp = Some(format);
+        self
+    }
+
+    /// Set the function that tells the time for the `ts` field.
+    ///
+    /// This defaults to [`SystemTime::now`], and is mostly useful to
+    /// get predictable timestamps in tests.
+    pub fn clock(mut self, clock: fn() -> SystemTime) -> Self {
+        self.options.clock = Some(clock);
+        self
+    }
+
+    /// Indent every line by `indent` spaces, before the prefix.
+    ///
+    /// The indentation can be changed later with
+    /// [`Logfmt::set_indent`], e.g. when entering and leaving a scope.
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Choose whether to start every line with the log level,
+    /// followed by a space, before the prefix.
+    ///
+    /// This guarantees a leading level token even with a custom
+    /// prefix. The default prefix already starts with the level, so
+    /// the default is to skip.
+    pub fn leading_level(mut self, leading: bool) -> Self {
+        self.options.leading_level = leading;
+        self
+    }
+
+    /// Choose whether to print the log level.
+    ///
+    /// The default prefix already prints it, so the default is to skip.
+    pub fn print_level(mut self, print: bool) -> Self {
+        self.options.print_level = print;
+        self
+    }
+
+    /// Follow the `level` field with a `level_num` field holding the
+    /// level's number in the given scheme, like `level=DEBG
+    /// level_num=7`.
+    ///
+    /// This only has an effect together with
+    /// [`print_level`](LogfmtBuilder::print_level).
+    pub fn level_num(mut self, numbering: LevelNumbering) -> Self {
+        self.options.level_num = Some(numbering);
+        self
+    }
+
+    /// Choose whether to print the record's tag as a `tag` field.
+    ///
+    /// The default prefix already prints it, so the default is to skip.
+    pub fn print_tag(mut self, print: bool) -> Self {
+        self.options.print_tag = print;
+        self
+    }
+
+    /// Set a tag to use for records that were logged without one, in
+    /// both the default prefix and the [`print_tag`](LogfmtBuilder::print_tag)
+    /// field.
+    pub fn default_tag(mut self, tag: &str) -> Self {
+        self.options.default_tag = Some(tag.to_string());
+        self
+    }
+
+    /// Choose how the default prefix marks the tag. The default is
+    /// [`TagMarker::Hash`], like `#tag`.
+    pub fn tag_marker(mut self, marker: TagMarker) -> Self {
+        self.options.tag_marker = marker;
+        self
+    }
+
+    /// Sets a function that decides, given a field's key and
+    /// rendered value, whether to quote the value even if it doesn't
+    /// need quotes:
+    ///
+    /// ```rust
+    /// # use slog_logfmt::Logfmt;
+    /// let drain = Logfmt::new(std::io::stdout())
+    ///     .force_quotes_when(|_key, value| value.len() > 20)
+    ///     .build();
+    /// ```
+    ///
+    /// Like [`force_quotes`](LogfmtBuilder::force_quotes), this only
+    /// has an effect with the [`EscapeStyle::Quote`] value style.
+    pub fn force_quotes_when<F>(mut self, quote: F) -> Self
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.options.force_quotes_when = Some(Arc::new(quote));
+        self
+    }
+
+    /// Choose whether to quote values only if they contain the
+    /// [field separator](LogfmtBuilder::field_separator), `=` or `"`,
+    /// leaving other characters like spaces or `:` unquoted. This is
+    /// meant for consumers that split lines on nothing but those.
+    /// Values with control characters like newlines are still quoted
+    /// (and escaped), so a record always stays on one line.
+    ///
+    /// Only applies to the default [`EscapeStyle::Quote`] value style.
+    pub fn minimal_quoting(mut self, minimal: bool) -> Self {
+        self.options.minimal_quoting = minimal;
+        self
+    }
+
+    /// Choose whether to escape `/` as `\/` in quoted values, like
+    /// JSON encoders often do, e.g. `path="\/srv\/www"`. Values that
+    /// aren't quoted keep their slashes as they are. Off by default.
+    pub fn escape_forward_slash(mut self, escape: bool) -> Self {
+        self.options.escape_forward_slash = escape;
+        self
+    }
+
+    /// Force quoting field values even if they don't contain quotable characters.
+    ///
+    /// Setting this option will surround values with quotes like `foo="bar"`.
+    pub fn force_quotes(mut self) -> Self {
+        self.options.force_quotes = true;
+        self
+    }
+
+    /// Set the string written between fields. The default is a single
+    /// space; some formats want something like `, ` instead.
+    ///
+    /// Values containing any of the separator's characters are
+    /// quoted.
+    pub fn field_separator(mut self, separator: &str) -> Self {
+        self.options.field_separator = separator.to_string();
+        self
+    }
+
+    /// Write the built-in fields that describe the record (like `ts`,
+    /// `level`, `delta_ms` or `_fields`) after the fields logged by
+    /// the application, with `separator` between the two groups, like
+    /// `user=alice -- ts=2020-03-12T08:00:00Z level=INFO`.
+    ///
+    /// The separator is written as it is, so it should include any
+    /// spaces around it. [`natural_sort`](LogfmtBuilder::natural_sort)
+    /// and [`key_priority`](LogfmtBuilder::key_priority) order the
+    /// fields within each group.
+    pub fn meta_separator(mut self, separator: &str) -> Self {
+        self.options.meta_separator = Some(separator.to_string());
+        self
+    }
+
+    /// Choose whether to turn `\r\n` line endings in values into a
+    /// plain `\n`, so they are escaped as a single `\n` instead of
+    /// `\r\n`.
+    ///
+    /// Lone carriage returns are never written raw, whatever the
+    /// escape style.
+    pub fn collapse_crlf(mut self, collapse: bool) -> Self {
+        self.options.collapse_crlf = collapse;
+        self
+    }
+
+    /// Replace characters in values with the given strings, before
+    /// they are escaped.
+    ///
+    /// This allows fitting values into stricter grammars than logfmt,
+    /// e.g. by mapping `=` to `\x3d`. Keys are not affected.
+    pub fn replace_chars(mut self, table: HashMap<char, &str>) -> Self {
+        self.options.char_replacements = table
+            .into_iter()
+            .map(|(ch, replacement)| (ch, replacement.to_string()))
+            .collect();
+        self
+    }
+
+    /// Choose how levels are labelled, in the default prefix and the
+    /// [`print_level`](LogfmtBuilder::print_level) field. The default
+    /// is [`LevelStyle::Short`].
+    ///
+    /// ```rust
+    /// # use slog::Level;
+    /// # use slog_logfmt::{LevelStyle, Logfmt};
+    /// let drain = Logfmt::new(std::io::stdout())
+    ///     .level_style(LevelStyle::Custom(|level| match level {
+    ///         Level::Critical | Level::Error => "🔴",
+    ///         Level::Warning => "🟡",
+    ///         _ => "🟢",
+    ///     }))
+    ///     .build();
+    /// ```
+    pub fn level_style(mut self, style: LevelStyle) -> Self {
+        self.options.level_style = style;
+        self
+    }
+
+    /// Choose whether to leave out the level of info records, treating
+    /// info as the normal case: their prefix starts without the
+    /// `INFO | ` label, and they get no `level` (or `level_num`)
+    /// field. Records of all other levels are labelled as usual.
+    pub fn level_only_non_info(mut self, only_non_info: bool) -> Self {
+        self.options.level_only_non_info = only_non_info;
+        self
+    }
+
+    /// Choose whether to color the level label in the prefix with
+    /// ANSI escape codes, e.g. red for errors.
+    ///
+    /// Fields are never colored, so their values stay the same. Color
+    /// is set per drain, which makes it a per-writer setting in a
+    /// [`dev_and_file_with`](Logfmt::dev_and_file_with) setup: the
+    /// terminal can show colors while the file stays plain.
+    pub fn color(mut self, color: bool) -> Self {
+        self.options.color = color;
+        self
+    }
+
+    /// Choose what to do with fields that have an empty key. The
+    /// default is [`EmptyKeyPolicy::Skip`].
+    pub fn empty_key(mut self, policy: EmptyKeyPolicy) -> Self {
+        self.options.empty_key = policy;
+        self
+    }
+
+    /// Choose how boolean values are written. The default is
+    /// [`BoolStyle::Value`].
+    pub fn bool_style(mut self, style: BoolStyle) -> Self {
+        self.options.bool_style = style;
+        self
+    }
+
+    /// Choose how errors are written. The default is
+    /// [`ErrorStyle::DisplayChain`].
+    ///
+    /// This applies to values logged via slog's `emit_error`, e.g.
+    /// with `#` in slog's macros, on drains that see them directly;
+    /// `slog-async` turns errors into their display chain before they
+    /// reach this drain.
+    pub fn error_style(mut self, style: ErrorStyle) -> Self {
+        self.options.error_style = style;
+        self
+    }
+
+    /// Choose whether to quote string values that look like numbers.
+    ///
+    /// A string like `"007"` is normally written as `k=007`, which
+    /// parsers may read as the number 7. With this on, it's written
+    /// as `k="007"` instead. Values logged as numbers are unaffected.
+    pub fn quote_numeric_strings(mut self, quote: bool) -> Self {
+        self.options.quote_numeric_strings = quote;
+        self
+    }
+
+    /// Write floating point values with exactly `digits` digits after
+    /// the decimal point, like `ratio=0.333` for a precision of 3.
+    ///
+    /// Numbers are always formatted the same regardless of locale:
+    /// with a `.` as the decimal point and no thousands separators.
+    pub fn float_precision(mut self, digits: usize) -> Self {
+        self.options.float_precision = Some(digits);
+        self
+    }
+
+    /// Shorten keys longer than `len` bytes.
+    ///
+    /// Long keys are cut off and end in `_` and four hex digits of a
+    /// hash of the full key, like `very_long_ke_a1b2`, so that keys
+    /// sharing a prefix don't collide. The hash is the same across
+    /// runs.
+    ///
+    /// Limits below 5 are raised to 5, the length of the hash suffix
+    /// on its own.
+    pub fn max_key_len(mut self, len: usize) -> Self {
+        self.options.max_key_len = Some(len.max(5));
+        self
+    }
+
+    /// Write records as tab-separated values instead of logfmt, with
+    /// one column for each of the given keys.
+    ///
+    /// Each line holds the values of the record's fields with those
+    /// keys, in order and without the `key=`; fields the record
+    /// doesn't have leave their column empty, and other fields are
+    /// left out. Backslashes, tabs and line breaks in values are
+    /// escaped as `\\`, `\t`, `\n` and `\r`. Neither indentation nor
+    /// the prefix are written, so to get the level or message, turn
+    /// on [`print_level`](LogfmtBuilder::print_level) or
+    /// [`print_msg`](LogfmtBuilder::print_msg) and name them as
+    /// columns.
+    pub fn tsv(mut self, columns: &[&str]) -> Self {
+        self.options.tsv = Some(columns.iter().map(|c| c.to_string()).collect());
+        self
+    }
+
+    /// Order the fields of each record by weight, lowest first.
+    ///
+    /// Keys that aren't in `priority` weigh
+    /// [`DEFAULT_KEY_PRIORITY`]; fields of equal weight keep the order
+    /// they were logged in. For example, weighing `ts` 0, `level` 1 and
+    /// `msg` 2 starts every record with those three fields.
+    pub fn key_priority(mut self, priority: HashMap<String, i32>) -> Self {
+        self.options.key_priority = priority;
+        self
+    }
+
+    /// Write fields under different keys, looked up by their logged
+    /// key in `renames`, e.g. to migrate from `lvl` to `level`
+    /// without changing the places that log them.
+    ///
+    /// Built-in fields like `msg` can be renamed, too. Redaction and
+    /// [`level_gated_keys`](LogfmtBuilder::level_gated_keys) still
+    /// see the logged key; everything else sees the new one.
+    pub fn rename_keys(mut self, renames: HashMap<String, String>) -> Self {
+        self.options.rename_keys = renames;
+        self
+    }
+
+    /// Choose whether to sort the fields of each record by key, in
+    /// natural order: runs of digits are compared by their numeric
+    /// value, so `item2` comes before `item10`.
+    ///
+    /// This also sorts the built-in fields like `level`; a
+    /// [`key_priority`](LogfmtBuilder::key_priority) is applied after
+    /// sorting, and can keep them in front.
+    pub fn natural_sort(mut self, sort: bool) -> Self {
+        self.options.natural_sort = sort;
+        self
+    }
+
+    /// Choose whether to write floating point values that have no
+    /// fractional part like integers, e.g. `x=3` instead of `x=3.0`.
+    ///
+    /// This only applies to values in the range of an `i64`, and takes
+    /// precedence over [`float_precision`](LogfmtBuilder::float_precision).
+    /// NaN and infinities are written as usual.
+    pub fn float_integer_shortcut(mut self, shortcut: bool) -> Self {
+        self.options.float_integer_shortcut = shortcut;
+        self
+    }
+
+    /// Pad the named fields to fixed widths, so their values line up
+    /// across records when reading the output as a human.
+    ///
+    /// Each entry names a key and the number of characters its
+    /// `key=value` rendering should take up; shorter fields are
+    /// followed by spaces, longer ones are written as they are.
+    /// Fields without an entry are written normally, and nothing is
+    /// padded at the end of a line.
+    pub fn columns(mut self, columns: &[(&str, usize)]) -> Self {
+        self.options.columns = columns
+            .iter()
+            .map(|(key, width)| (key.to_string(), *width))
+            .collect();
+        self
+    }
+
+    /// Choose whether to namespace the fields of tagged records with
+    /// their tag: a record tagged `http` is written with fields like
+    /// `http.method=GET http.status=200`, using the
+    /// [nest separator](LogfmtBuilder::nest_separator) after the tag.
+    ///
+    /// This applies to the logger's and the record's own key/value
+    /// pairs, not to fields added by the drain (like `ts` or
+    /// `level`). The redactor still sees the keys without the tag.
+    pub fn tag_as_key_prefix(mut self, prefix: bool) -> Self {
+        self.options.tag_as_key_prefix = prefix;
+        self
+    }
+
+    /// Write at most `max` of the logger's key/value pairs (its
+    /// context) per record, dropping the rest.
+    ///
+    /// The pairs are kept starting from the most specific: those of
+    /// the innermost logger come first, and within each logger's
+    /// `o!()`, the ones listed last come first. The record's own
+    /// key/value pairs are unaffected, as is the
+    /// [`logger_path`](LogfmtBuilder::track_logger_path).
+    pub fn max_context_fields(mut self, max: usize) -> Self {
+        self.options.max_context_fields = Some(max);
+        self
+    }
+
+    /// Choose whether to write records vertically: the prefix on a
+    /// line of its own, followed by each field on its own line,
+    /// indented by two spaces:
+    ///
+    /// ```text
+    /// DEBG | request done
+    ///   status=200
+    ///   user=alice
+    /// ```
+    ///
+    /// This is meant for reading records with many fields while
+    /// debugging; it isn't logfmt anymore, and
+    /// [`columns`](LogfmtBuilder::columns) and
+    /// [`prefix_dedup`](LogfmtBuilder::prefix_dedup) don't apply.
+    pub fn vertical(mut self, vertical: bool) -> Self {
+        self.options.vertical = vertical;
+        self
+    }
+
+    /// Choose whether to right-align fields in
+    /// [`columns`](LogfmtBuilder::columns) whose value is a number, by
+    /// padding them with spaces in front of the key: with a width of
+    /// 5 for `n`, the values 7 and 123 are written as `  n=7` and
+    /// `n=123`. Other fields in columns are still padded after them.
+    pub fn right_align_numbers(mut self, right_align: bool) -> Self {
+        self.options.right_align_numbers = right_align;
+        self
+    }
+
+    /// Limit the combined length of all field values in a record to
+    /// `budget` bytes. Built-in fields like `ts` and `level` aren't
+    /// counted, and never truncated.
+    ///
+    /// When a record goes over the budget, the longest values are
+    /// truncated first, and a `_truncated=N` field is appended to the
+    /// record, counting how many values were cut.
+    pub fn value_budget(mut self, budget: usize) -> Self {
+        self.options.value_budget = Some(budget);
+        self
+    }
+
+    /// Check that no field value is longer than `limit` bytes, to
+    /// catch accidentally huge fields in tests.
+    ///
+    /// Unlike [`value_budget`](LogfmtBuilder::value_budget), this
+    /// doesn't shorten anything: in debug builds, logging a record
+    /// with an oversized value panics, and in release builds, the
+    /// record is written as it is with an `_oversized_keys` field
+    /// listing the offending keys separated by commas. Use
+    /// [`mark_oversized_fields`](LogfmtBuilder::mark_oversized_fields)
+    /// to get the release behaviour in debug builds too.
+    pub fn strict_field_limits(mut self, limit: usize) -> Self {
+        self.options.strict_field_limit = Some((limit, cfg!(debug_assertions)));
+        self
+    }
+
+    /// Like [`strict_field_limits`](LogfmtBuilder::strict_field_limits),
+    /// but never panics: records with values longer than `limit` bytes
+    /// always get an `_oversized_keys` field instead.
+    pub fn mark_oversized_fields(mut self, limit: usize) -> Self {
+        self.options.strict_field_limit = Some((limit, false));
+        self
+    }
+
+    /// Add fields with default values to records that don't have
+    /// them, e.g. `tenant=unknown`, so every record has the same keys.
+    ///
+    /// A default is added after the record's own fields if none of
+    /// its fields (including the logger's) is written under that key.
+    pub fn default_fields(mut self, defaults: &[(&str, &str)]) -> Self {
+        self.options.default_fields = defaults
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        self
+    }
+
+    /// Add a field `key` whose value is computed from the record's
+    /// other fields, e.g. a `latency_bucket` from `latency_ms`.
+    ///
+    /// `derive` is called with the record's rendered (and redacted)
+    /// fields, including built-in ones like `level` and the fields
+    /// added by earlier derived fields, and the field is left out if
+    /// it returns `None`. Derived fields are appended in the order
+    /// they were added.
+    pub fn derived_field<F>(mut self, key: &str, derive: F) -> Self
+    where
+        F: Fn(&FieldMap) -> Option<String> + Send + Sync + 'static,
+    {
+        self.options
+            .derived_fields
+            .push((key.to_string(), Arc::new(derive)));
+        self
+    }
+
+    /// Choose how bytes that aren't valid UTF-8 in a
+    /// [`BytesValue`](values::BytesValue) are rendered.
+    ///
+    /// The default is [`InvalidUtf8Policy::Replace`].
+    pub fn invalid_utf8(mut self, policy: InvalidUtf8Policy) -> Self {
+        self.options.invalid_utf8 = policy;
+        self
+    }
+
+    /// Choose whether to append a `_fields=N` field to each record,
+    /// counting the key/value pairs that were logged (after
+    /// redaction).
+    pub fn report_field_count(mut self, report: bool) -> Self {
+        self.options.report_field_count = report;
+        self
+    }
+
+    /// Choose whether to append a `_redacted_keys` field to records
+    /// that had fields skipped or masked by the
+    /// [redactor](LogfmtBuilder::redact), listing their keys
+    /// separated by commas, like `_redacted_keys="password,token"`.
+    pub fn list_redacted_keys(mut self, list: bool) -> Self {
+        self.options.list_redacted_keys = list;
+        self
+    }
+
+    /// Choose whether to append a `delta_ms` field to each record,
+    /// holding the milliseconds elapsed since this drain logged the
+    /// previous record (`0` for the first one).
+    ///
+    /// The delta is measured when the record reaches this drain, so
+    /// behind `slog-async` it reflects the order in which the worker
+    /// thread processes records, not when they were logged.
+    pub fn with_delta(mut self, delta: bool) -> Self {
+        self.options.with_delta = delta;
+        self
+    }
+
+    /// Choose whether to append a `line` field to each record,
+    /// numbering the records written to its writer, starting at `1`.
+    ///
+    /// Unlike a process-wide sequence number, the count starts over
+    /// when the writer is swapped out with
+    /// [`replace_writer`](Logfmt::replace_writer), so after rotating
+    /// a log file, the records in each file are numbered from `1`.
+    /// Records sent to a [split writer](LogfmtBuilder::split_writer)
+    /// are counted separately.
+    pub fn line_numbers(mut self, line_numbers: bool) -> Self {
+        self.options.line_numbers = line_numbers;
+        self
+    }
+
+    /// Choose whether to append a `cpu_ms` field to each record,
+    /// holding the milliseconds of CPU time that the whole process
+    /// (all of its threads) has used so far. Subtracting the values
+    /// of two records gives the CPU time spent between them.
+    ///
+    /// This is read with `clock_gettime(CLOCK_PROCESS_CPUTIME_ID)`,
+    /// which is only available on Linux, Android, macOS, iOS and
+    /// FreeBSD; elsewhere, the field is left out. Behind
+    /// `slog-async`, the time is read when the worker thread gets to
+    /// the record.
+    #[cfg(feature = "cpu-time")]
+    pub fn with_cpu_time(mut self, cpu_time: bool) -> Self {
+        self.options.with_cpu_time = cpu_time;
+        self
+    }
+
+    /// Append a `queue_depth` field to each record, holding the value
+    /// of `gauge` at the time the record is written.
+    ///
+    /// `slog-async` doesn't expose how many records are waiting in
+    /// its queue, so the gauge has to be maintained by the caller,
+    /// e.g. by counting records in a drain placed in front of the
+    /// async one and decrementing in one behind it.
+    pub fn queue_depth_gauge(mut self, gauge: Arc<AtomicUsize>) -> Self {
+        self.options.queue_depth = Some(gauge);
+        self
+    }
+
+    /// For each of the given keys with a numeric value, add a field
+    /// holding the difference to the key's value in the previous
+    /// record that had it, like `requests=1037 requests_delta=37`.
+    ///
+    /// This is meant for counters, to make rates easy to read off.
+    /// The first record with a key gets no delta field.
+    pub fn delta_keys(mut self, keys: &[&str]) -> Self {
+        self.options.delta_keys = keys.iter().map(|key| key.to_string()).collect();
+        self
+    }
+
+    /// Log only one in every `rate` records, dropping the rest.
+    ///
+    /// The first record is always logged. Logged records carry a
+    /// `_sample_rate=N` field, so that anything counting them
+    /// downstream can weigh them accordingly. A rate of 0 is treated
+    /// like 1, logging everything.
+    pub fn sample_rate(mut self, rate: u64) -> Self {
+        self.options.sample_rate = Some(rate.max(1));
+        self
+    }
+
+    /// Log at most `per_second` records per second, dropping the rest.
+    ///
+    /// Up to a second's worth of records can be logged in a burst.
+    /// The first record logged after some were dropped carries a
+    /// `_rate_limited=N` field with the number of records dropped.
+    /// Records at or above the level set with
+    /// [`rate_limit_exempt`](LogfmtBuilder::rate_limit_exempt) are
+    /// always logged and don't count against the limit.
+    ///
+    /// With [`deterministic`](LogfmtBuilder::deterministic) output,
+    /// the clock stands still, so only the first burst is logged.
+    pub fn rate_limit(mut self, per_second: u32) -> Self {
+        self.options.rate_limit = Some(per_second);
+        self
+    }
+
+    /// Exempt records at or above `level` from the
+    /// [`rate_limit`](LogfmtBuilder::rate_limit).
+    pub fn rate_limit_exempt(mut self, level: Level) -> Self {
+        self.options.rate_limit_exempt = Some(level);
+        self
+    }
+
+    /// After every `every` records, write a summary line counting
+    /// them by level, like `_summary=1 total=1000 errors=12
+    /// warnings=30`.
+    ///
+    /// `errors` includes critical records. Only records that are
+    /// logged count, so paused or sampled-out records don't. No
+    /// summary is written with [`tsv`](LogfmtBuilder::tsv), since it
+    /// doesn't fit the columns.
+    pub fn summary_every(mut self, every: usize) -> Self {
+        self.options.summary_every = Some(every.max(1));
+        self
+    }
+
+    /// Sets a function to call after each record has been written
+    /// and flushed, e.g. to count records in a metric.
+    ///
+    /// It isn't called for records that were dropped (by sampling or
+    /// while [paused](Logfmt::set_paused)) or that failed to be
+    /// written.
+    pub fn after_record<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&Record) + Send + Sync + 'static,
+    {
+        self.options.after_record = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a function that can decide to keep or drop a record
+    /// regardless of the [`sample_rate`](LogfmtBuilder::sample_rate),
+    /// for example to log all records of a flagged request.
+    ///
+    /// Forced records don't count towards the sample rate, and when
+    /// sampling is on, are marked with `_sample_rate=1`.
+    pub fn sample_override<F>(mut self, decide: F) -> Self
+    where
+        F: Fn(&Record) -> SampleDecision + Send + Sync + 'static,
+    {
+        self.options.sample_override = Some(Arc::new(decide));
+        self
+    }
+
+    /// Choose how keys are escaped. The default is
+    /// [`EscapeStyle::Raw`], writing keys as they are.
+    pub fn key_escape_style(mut self, style: EscapeStyle) -> Self {
+        self.options.key_escape = style;
+        self
+    }
+
+    /// Choose how values are escaped. The default is
+    /// [`EscapeStyle::Quote`]; [`force_quotes`](LogfmtBuilder::force_quotes)
+    /// only has an effect with that style.
+    pub fn value_escape_style(mut self, style: EscapeStyle) -> Self {
+        self.options.value_escape = style;
+        self
+    }
+
+    /// Choose whether to annotate each key with the type its value
+    /// was logged as, like `count:i64=5` or `name:str=asf`.
+    ///
+    /// The type names are those of slog's `Serializer::emit_*`
+    /// methods. Values that are formatted via `Display` or `Debug`
+    /// (`%` and `?` in slog's macros) are annotated as `fmt`. Note
+    /// that this changes the key grammar, so logfmt parsers will
+    /// read the annotation as part of the key.
+    pub fn type_suffix(mut self, annotate: bool) -> Self {
+        self.options.type_suffix = annotate;
+        self
+    }
+
+    /// Choose what to write between each key and value, depending on
+    /// the kind of value, e.g. `count=5` but `name: asf`.
+    ///
+    /// By default, every field uses `=`. Anything else isn't logfmt
+    /// anymore, so this is meant for output read by people.
+    pub fn assignment_style<F>(mut self, style: F) -> Self
+    where
+        F: Fn(TypeClass) -> &'static str + Send + Sync + 'static,
+    {
+        self.options.assignment_style = Some(Arc::new(style));
+        self
+    }
+
+    /// Set the string that joins the path components of nested keys,
+    /// like `req.user.id`. The default is `.`.
+    pub fn nest_separator(mut self, separator: &str) -> Self {
+        self.options.nest_separator = separator.to_string();
+        self
+    }
+
+    /// Stop flattening nested values after `depth` levels below the
+    /// logged key; anything deeper is logged as one JSON value.
+    ///
+    /// For example, with a depth of 2, `{"a": {"b": {"c": 1}}}` logged
+    /// as `f` is written as `f.a.b="{\"c\":1}"`.
+    #[cfg(feature = "nested-values")]
+    pub fn max_nest_depth(mut self, depth: usize) -> Self {
+        self.options.max_nest_depth = Some(depth);
+        self
+    }
+
+    /// Choose whether to leave out nested values that are empty maps
+    /// or sequences, instead of logging them as `key={}` or `key=[]`.
+    #[cfg(feature = "nested-values")]
+    pub fn skip_empty_nested(mut self, skip: bool) -> Self {
+        self.options.skip_empty_nested = skip;
+        self
+    }
+
+    /// Choose whether to survive values whose `Display` or `Debug`
+    /// implementation panics.
+    ///
+    /// With this on, formatting each value happens behind a
+    /// [`catch_unwind`](std::panic::catch_unwind) boundary, and a
+    /// value that panics is logged as `key="<panicked>"` instead of
+    /// unwinding out of the logging call. The panic hook still runs.
+    ///
+    /// Drains that format values before they reach this one (like
+    /// `slog-async`) are not covered by this.
+    pub fn catch_value_panics(mut self, catch: bool) -> Self {
+        self.options.catch_value_panics = catch;
+        self
+    }
+
+    /// Choose whether to fold errors into the message of error-level
+    /// records.
+    ///
+    /// With this on, fields logged as errors (via slog's
+    /// `emit_error`, e.g. with `#` in slog's macros) on records at
+    /// `ERRO` or `CRIT` level aren't written as separate fields;
+    /// instead, their chains are appended to the message, like
+    /// `request failed: timeout: connection refused`.
+    pub fn errors_into_msg(mut self, fold: bool) -> Self {
+        self.options.errors_into_msg = fold;
+        self
+    }
+
+    /// Choose whether to generate a correlation ID for records that
+    /// don't carry one.
+    ///
+    /// When a record (including its logger's context) has no field
+    /// named by [`correlation_id_key`](LogfmtBuilder::correlation_id_key),
+    /// a freshly generated [ULID](https://github.com/ulid/spec) is
+    /// logged under that key.
+    pub fn auto_correlation_id(mut self, generate: bool) -> Self {
+        self.options.auto_correlation_id = generate;
+        self
+    }
+
+    /// Choose whether to add a `logger_path` field, joining the
+    /// `component` values of a logger and all its parents with dots,
+    /// like `logger_path=app.http.handler`.
+    ///
+    /// slog doesn't keep track of logger lineage, so this relies on
+    /// each logger in the tree adding its name as a `component`
+    /// context value; see also
+    /// [`logger_path_key`](LogfmtBuilder::logger_path_key).
+    pub fn track_logger_path(mut self, track: bool) -> Self {
+        self.options.logger_path_key = if track {
+            self.options
+                .logger_path_key
+                .or_else(|| Some("component".to_string()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Set the context key that [`track_logger_path`](LogfmtBuilder::track_logger_path)
+    /// builds the path from, and turn tracking on. The default key is
+    /// `component`.
+    pub fn logger_path_key(mut self, key: &str) -> Self {
+        self.options.logger_path_key = Some(key.to_string());
+        self
+    }
+
+    /// Set the key holding correlation IDs. The default is `trace_id`.
+    pub fn correlation_id_key(mut self, key: &str) -> Self {
+        self.options.correlation_id_key = key.to_string();
+        self
+    }
+
+    /// Name and order the standard fields like `slog-json` does, to
+    /// keep queries the same across both crates' output.
+    ///
+    /// This turns off the prefix and starts each record with `ts`
+    /// (RFC 3339, in UTC), `level` (as slog's short, upper-case
+    /// names like `INFO`) and `msg` fields. The output is still
+    /// logfmt.
+    pub fn slog_json_compat(self) -> Self {
+        self.no_prefix()
+            .timestamp(TimestampFormat::Rfc3339)
+            .print_level(true)
+            .print_msg(true)
+    }
+
+    /// Add a `build` field identifying the running build, like a git
+    /// commit, to every record.
+    ///
+    /// To set it at compile time, use something like
+    /// `build_info(env!("GIT_SHA"))`.
+    pub fn build_info(mut self, build: &str) -> Self {
+        self.options.build_info = Some(build.to_string());
+        self
+    }
+
+    /// Choose whether to add an `instance` field to every record,
+    /// holding an ID that is generated once when the drain is built,
+    /// e.g. to tell apart the logs of several instances of a service.
+    ///
+    /// The ID is a random [ULID](https://github.com/ulid/spec); set a
+    /// fixed one with [`instance_id`](LogfmtBuilder::instance_id).
+    pub fn with_instance_id(mut self, instance_id: bool) -> Self {
+        self.options.with_instance_id = instance_id;
+        self
+    }
+
+    /// Add an `instance` field with the given value to every record,
+    /// instead of a generated one.
+    pub fn instance_id(mut self, id: &str) -> Self {
+        self.options.with_instance_id = true;
+        self.options.instance_id = Some(id.to_string());
+        self
+    }
+
+    /// Choose whether to add a `pid` field to every record, holding
+    /// the ID of the process (or the one set with
+    /// [`pid`](LogfmtBuilder::pid)).
+    pub fn with_pid(mut self, pid: bool) -> Self {
+        self.options.with_pid = pid;
+        self
+    }
+
+    /// Add a `pid` field with the given value to every record,
+    /// instead of the ID of the running process.
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.options.with_pid = true;
+        self.options.pid = Some(pid);
+        self
+    }
+
+    /// Choose whether to add a `host` field to every record, holding
+    /// the machine's hostname (or the one set with
+    /// [`hostname`](LogfmtBuilder::hostname)).
+    ///
+    /// The hostname is looked up once when the drain is built, from
+    /// `/proc/sys/kernel/hostname` or the `HOSTNAME` or
+    /// `COMPUTERNAME` environment variables. If none of them has it,
+    /// no `host` field is added.
+    pub fn with_hostname(mut self, hostname: bool) -> Self {
+        self.options.with_hostname = hostname;
+        self
+    }
+
+    /// Add a `host` field with the given value to every record,
+    /// instead of looking up the hostname.
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.options.with_hostname = true;
+        self.options.hostname = Some(hostname.to_string());
+        self
+    }
+
+    /// Choose whether to add a `seq` field to each record, numbering
+    /// all records logged by the drains of this process, starting at
+    /// `1`, so that their order can be restored after the lines got
+    /// mixed up.
+    pub fn with_seq(mut self, seq: bool) -> Self {
+        self.options.with_seq = seq;
+        self
+    }
+
+    /// Choose whether to add an `elapsed_ms` field to each record,
+    /// holding the milliseconds since the drain was built.
+    pub fn with_elapsed(mut self, elapsed: bool) -> Self {
+        self.options.with_elapsed = elapsed;
+        self
+    }
+
+    /// Start the output with a single line of JSON describing the
+    /// format of the records after it, so that consumers of a log
+    /// file can configure themselves:
+    ///
+    /// ```text
+    /// {"format":"logfmt","keys":{"level":"level","msg":"msg","ts":"ts"},"fields":["ts","level"],"levels":[{"name":"critical","label":"CRIT"},...]}
+    /// ```
+    ///
+    /// `keys` holds the keys of the built-in fields, and `fields` the
+    /// keys that records are known to start with, in order: the
+    /// [`tsv`](LogfmtBuilder::tsv) columns, or the enabled built-in
+    /// fields plus those named in [`columns`](LogfmtBuilder::columns)
+    /// and [`key_priority`](LogfmtBuilder::key_priority). Each level
+    /// is listed with its label and, with
+    /// [`level_num`](LogfmtBuilder::level_num), its number.
+    ///
+    /// The header is written to the main writer, right before the
+    /// first record that is written.
+    pub fn schema_header(mut self, schema_header: bool) -> Self {
+        self.options.schema_header = schema_header;
+        self
+    }
+
+    /// Take the `build` field's value from the environment variable
+    /// `var` of the running process. If the variable isn't set (or not
+    /// valid unicode), no `build` field is added.
+    pub fn build_info_from_env(mut self, var: &str) -> Self {
+        self.options.build_info = std::env::var(var).ok();
+        self
+    }
+
+    /// Make the output byte-stable across runs, for golden/snapshot tests.
+    ///
+    /// Any field derived from the environment (clocks, process and
+    /// host identity, counters) is rendered with a fixed value
+    /// instead of a live one: `ts` is the unix epoch, `pid` is `0`,
+    /// `host` is `localhost`, durations like `elapsed_ms` and
+    /// `delta_ms` are `0`, and `seq` counts only this drain's records.
+    /// The formatting of messages and key/value pairs is unaffected.
+    pub fn deterministic(mut self) -> Self {
+        self.options.deterministic = true;
+        self
+    }
+}
+
+/// Copies every field of `$other` that differs from its default value
+/// into `$options`.
+macro_rules! overlay(
+    ($options:expr, $other:expr; $($field:ident),* $(,)?) => {{
+        let default = Options::default();
+        $(
+            if $other.$field != default.$field {
+                $options.$field = $other.$field.clone();
+            }
+        )*
+    }};
+);
+
+impl Options {
+    /// Overlays the options that `other` sets to non-default values
+    /// onto these.
+    fn merge(&mut self, other: &Options) {
+        if let Prefix::Custom(_) = other.prefix {
+            self.prefix = other.prefix;
+        }
+        if !matches!(other.level_style, LevelStyle::Short) {
+            self.level_style = other.level_style;
+        }
+        if other.clock.is_some() {
+            self.clock = other.clock;
+        }
+        if other.redactor.is_some() {
+            self.redactor = other.redactor;
+        }
+        if other.level_gate.is_some() {
+            self.level_gate = other.level_gate.clone();
+        }
+        if other.queue_depth.is_some() {
+            self.queue_depth = other.queue_depth.clone();
+        }
+        if other.sample_override.is_some() {
+            self.sample_override = other.sample_override.clone();
+        }
+        if other.force_quotes_when.is_some() {
+            self.force_quotes_when = other.force_quotes_when.clone();
+        }
+        if other.value_redactor.is_some() {
+            self.value_redactor = other.value_redactor.clone();
+        }
+        if other.assignment_style.is_some() {
+            self.assignment_style = other.assignment_style.clone();
+        }
+        if other.after_record.is_some() {
+            self.after_record = other.after_record.clone();
+        }
+        if other.error_fields.is_some() {
+            self.error_fields = other.error_fields.clone();
+        }
+        if !other.derived_fields.is_empty() {
+            self.derived_fields = other.derived_fields.clone();
+        }
+        overlay!(self, other;
+            prefix_msg,
+            quote_prefix_msg,
+            color,
+            level_only_non_info,
+            tag_marker,
+            prefix_only_tagged,
+            prefix_dedup,
+            collapse_msg_whitespace,
+            escape_prefix_separator,
+            timestamp,
+            leading_level,
+            print_level,
+            level_num,
+            print_msg,
+            print_tag,
+            default_tag,
+            force_quotes,
+            field_separator,
+            meta_separator,
+            collapse_crlf,
+            char_replacements,
+            quote_numeric_strings,
+            minimal_quoting,
+            escape_forward_slash,
+            bool_style,
+            empty_key,
+            error_style,
+            float_precision,
+            float_integer_shortcut,
+            columns,
+            vertical,
+            right_align_numbers,
+            prefix_fields,
+            tsv,
+            key_priority,
+            natural_sort,
+            max_key_len,
+            rename_keys,
+            default_fields,
+            key_escape,
+            value_escape,
+            type_suffix,
+            nest_separator,
+            tag_as_key_prefix,
+            max_nest_depth,
+            skip_empty_nested,
+            catch_value_panics,
+            errors_into_msg,
+            cache_redaction,
+            value_budget,
+            strict_field_limit,
+            invalid_utf8,
+            report_field_count,
+            list_redacted_keys,
+            with_delta,
+            line_numbers,
+            delta_keys,
+            with_cpu_time,
+            sample_rate,
+            rate_limit,
+            rate_limit_exempt,
+            summary_every,
+            auto_correlation_id,
+            logger_path_key,
+            max_context_fields,
+            build_info,
+            with_instance_id,
+            with_pid,
+            pid,
+            with_hostname,
+            hostname,
+            with_seq,
+            with_elapsed,
+            instance_id,
+            correlation_id_key,
+            schema_header,
+            deterministic,
+        );
+    }
+
+    fn format_float<F: std::fmt::Display + Into<f64> + Copy>(&self, val: F) -> String {
+        let wide: f64 = val.into();
+        // `i64::MAX as f64` rounds up to 2^63, which is out of range.
+        let in_range = wide >= i64::MIN as f64 && wide < i64::MAX as f64;
+        if self.float_integer_shortcut && wide.fract() == 0.0 && in_range {
+            return (wide as i64).to_string();
+        }
+        match self.float_precision {
+            Some(digits) => format!("{:.*}", digits, val),
+            None => val.to_string(),
+        }
+    }
+
+    /// Shortens `key` to at most `max_key_len` bytes, ending it in a
+    /// hash of the full key so that different long keys stay distinct.
+    fn limit_key(&self, key: String) -> String {
+        let max = match self.max_key_len {
+            Some(max) if key.len() > max => max,
+            _ => return key,
+        };
+        let suffix = format!("_{:04x}", fnv1a(key.as_bytes()) & 0xffff);
+        let mut end = max.saturating_sub(suffix.len());
+        while !key.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut limited = key[..end].to_string();
+        limited.push_str(&suffix);
+        limited
+    }
+
+    /// The key that a built-in field is written under, after
+    /// [`rename_keys`](LogfmtBuilder::rename_keys).
+    fn renamed_key<'k>(&'k self, key: &'k str) -> &'k str {
+        self.rename_keys.get(key).map_or(key, String::as_str)
+    }
+
+    /// The key to write a field under, which is the placeholder for
+    /// empty keys if there is one.
+    fn field_key(&self, key: &str) -> String {
+        if let Some(renamed) = self.rename_keys.get(key) {
+            return renamed.clone();
+        }
+        match self.empty_key {
+            EmptyKeyPolicy::Placeholder(placeholder) if key.is_empty() => placeholder.to_string(),
+            _ => key.to_string(),
+        }
+    }
+
+    /// The key that a field added by the drain itself is written
+    /// under, after renaming and shortening.
+    fn output_key(&self, key: &str) -> String {
+        self.limit_key(self.field_key(key))
+    }
+
+    /// A built-in field, under its [output key](Options::output_key).
+    fn meta_field(&self, key: &str, ty: &'static str, value: impl Into<String>) -> Field {
+        Field::meta(self.output_key(key), ty, value)
+    }
+
+    /// The record's tag, or the default tag if it has none.
+    fn tag<'r>(&'r self, record: &'r Record) -> &'r str {
+        match (record.tag(), &self.default_tag) {
+            ("", Some(default)) => default,
+            (tag, _) => tag,
+        }
+    }
+
+    fn redaction(&self, key: &str) -> Redaction {
+        let redact = match self.redactor {
+            Some(redact) => redact,
+            None => return Redaction::Plain,
+        };
+        if !self.cache_redaction {
+            return redact(&key);
+        }
+        let mut cache = self.redaction_cache.lock().unwrap();
+        if let Some(decision) = cache.get(key) {
+            return *decision;
+        }
+        let decision = redact(&key);
+        cache.insert(key.to_string(), decision);
+        decision
+    }
+
+    fn now(&self) -> SystemTime {
+        if self.deterministic {
+            UNIX_EPOCH
+        } else {
+            self.clock.map_or_else(SystemTime::now, |clock| clock())
+        }
+    }
+
+    /// Writes the prefix; `msg` replaces the record's message in the
+    /// default prefix, and `fields` are searched for prefix fields.
+    fn write_prefix(
+        &self,
+        io: &mut dyn io::Write,
+        rec: &Record,
+        msg: Option<&str>,
+        fields: &[Field],
+    ) -> slog::Result {
+        if self.leading_level && self.shows_level(rec.level()) {
+            write!(io, "{} ", self.prefix_level(rec.level()))?;
+        }
+        match self.prefix {
+            Prefix::Default => self.default_prefix(io, rec, msg, fields),
+            Prefix::Custom(prefix) => prefix(io, rec),
+        }
+    }
+
+    fn default_prefix(
+        &self,
+        io: &mut dyn io::Write,
+        rec: &Record,
+        msg: Option<&str>,
+        fields: &[Field],
+    ) -> slog::Result {
+        if self.shows_level(rec.level()) {
+            write!(io, "{} | ", self.prefix_level(rec.level()))?;
+        }
+        let tag = self.tag(rec);
+        if !tag.is_empty() {
+            let (before, after) = self.tag_marker.parts();
+            write!(io, "{}{}{}\t", before, self.escape_prefix_text(tag), after)?;
+        }
+        for key in &self.prefix_fields {
+            if let Some(field) = fields.iter().find(|f| f.key == *key) {
+                self.write_field(io, field, true)?;
+                io.write_all(b"\t")?;
+            }
+        }
+        if self.prefix_msg {
+            if msg.is_none()
+                && !self.collapse_msg_whitespace
+                && !self.escape_prefix_separator
+                && !self.quote_prefix_msg
+            {
+                write!(io, "{}\t", rec.msg())?;
+            } else {
+                let mut msg = msg.map_or_else(|| rec.msg().to_string(), str::to_string);
+                if self.collapse_msg_whitespace {
+                    msg = collapse_whitespace(&msg);
+                }
+                if self.quote_prefix_msg {
+                    write!(io, "{}\t", optionally_quote(&msg, true))?;
+                } else {
+                    write!(io, "{}\t", self.escape_prefix_text(&msg))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether records of `level` are labelled with it, see
+    /// [`level_only_non_info`](LogfmtBuilder::level_only_non_info).
+    fn shows_level(&self, level: Level) -> bool {
+        !(self.level_only_non_info && level == Level::Info)
+    }
+
+    /// The level label to write in the prefix, colored if the drain
+    /// is configured to.
+    fn prefix_level(&self, level: Level) -> Cow<'static, str> {
+        let label = self.level_style.label(level);
+        if !self.color {
+            return label.into();
+        }
+        let color = match level {
+            Level::Critical => "1;31",
+            Level::Error => "31",
+            Level::Warning => "33",
+            Level::Info => "32",
+            Level::Debug => "36",
+            Level::Trace => "2",
+        };
+        format!("\x1b[{}m{}\x1b[0m", color, label).into()
+    }
+
+    /// Escapes the default prefix's ` | ` separator in text that goes
+    /// into the prefix, if the drain is configured to.
+    fn escape_prefix_text<'t>(&self, text: &'t str) -> Cow<'t, str> {
+        if self.escape_prefix_separator && text.contains(" | ") {
+            text.replace(" | ", " \\| ").into()
+        } else {
+            text.into()
+        }
+    }
+
+    /// Removes error fields from an error-level record and returns its
+    /// message with their chains appended, if the drain is configured
+    /// to do that. The `msg` field is updated to match.
+    fn errors_into_msg(&self, record: &Record, fields: &mut Vec<Field>) -> Option<String> {
+        if !self.errors_into_msg || !record.level().is_at_least(Level::Error) {
+            return None;
+        }
+        let mut msg = record.msg().to_string();
+        let mut found = false;
+        fields.retain(|field| {
+            if field.ty != "error" {
+                return true;
+            }
+            msg.push_str(": ");
+            msg.push_str(&field.value);
+            found = true;
+            false
+        });
+        if !found {
+            return None;
+        }
+        let msg_key = self.renamed_key("msg");
+        if let Some(field) = fields
+            .iter_mut()
+            .find(|f| f.key == msg_key && f.ty == "fmt")
+        {
+            field.value = msg.clone();
+        }
+        Some(msg)
+    }
+
+    /// Collects the fields of a record, along with its message if
+    /// [`errors_into_msg`](LogfmtBuilder::errors_into_msg) changed it.
+    fn collect_fields(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> slog::Result<(Vec<Field>, Option<String>)> {
+        let mut fields = Vec::new();
+        let mut serializer = FieldCollector {
+            fields: &mut fields,
+            options: self,
+            level: Some(record.level()),
+            redacted: Vec::new(),
+            key_prefix: String::new(),
+        };
+        if let Some(format) = self.timestamp {
+            let mut ts = String::new();
+            format.write(self.now(), &mut ts)?;
+            serializer.push(&"ts", "str", ts);
+        }
+        if self.print_level && self.shows_level(record.level()) {
+            let lvl = o!("level" => self.level_style.label(record.level()));
+            lvl.serialize(record, &mut serializer)?;
+            if let Some(numbering) = self.level_num {
+                let num = o!("level_num" => numbering.number(record.level()));
+                num.serialize(record, &mut serializer)?;
+            }
+        }
+        if self.print_msg {
+            record.msg().serialize(
+                record,
+                #[allow(clippy::useless_conversion)] // necessary for dynamic-keys
+                "msg".into(),
+                &mut serializer,
+            )?;
+        }
+        if self.print_tag {
+            let tag = o!("tag" => self.tag(record).to_string());
+            tag.serialize(record, &mut serializer)?;
+        }
+        if let Some(build) = &self.build_info {
+            serializer.push(&"build", "str", build.clone());
+        }
+        if let Some(instance) = &self.instance_id {
+            let instance = if self.deterministic {
+                id::encode(0)
+            } else {
+                instance.clone()
+            };
+            serializer.push(&"instance", "str", instance);
+        }
+        if let Some(pid) = self.pid {
+            let pid = if self.deterministic { 0 } else { pid };
+            serializer.push(&"pid", "u32", pid.to_string());
+        }
+        if let Some(host) = &self.hostname {
+            let host = if self.deterministic {
+                "localhost".to_string()
+            } else {
+                host.clone()
+            };
+            serializer.push(&"host", "str", host);
+        }
+        let tag = self.tag(record);
+        let key_prefix = if self.tag_as_key_prefix && !tag.is_empty() {
+            format!("{}{}", tag, self.nest_separator)
+        } else {
+            String::new()
+        };
+        let context_start = serializer.fields.len();
+        for field in serializer.fields.iter_mut() {
+            field.meta = true;
+        }
+        serializer.key_prefix = key_prefix.clone();
+        logger_values.serialize(record, &mut serializer)?;
+        serializer.key_prefix.clear();
+        let mut logger_path = None;
+        if let Some(key) = &self.logger_path_key {
+            let key = self.limit_key(format!("{}{}", key_prefix, self.field_key(key)));
+            // Logger values come from the innermost logger outwards.
+            let mut components: Vec<&str> = serializer.fields[context_start..]
+                .iter()
+                .filter(|f| f.key == key)
+                .map(|f| f.value.as_str())
+                .collect();
+            if !components.is_empty() {
+                components.reverse();
+                logger_path = Some(components.join("."));
+            }
+        }
+        if let Some(max) = self.max_context_fields {
+            serializer.fields.truncate(context_start + max);
+        }
+        if let Some(path) = logger_path {
+            serializer.push(&"logger_path", "str", path);
+        }
+        serializer.key_prefix = key_prefix;
+        record.kv().serialize(record, &mut serializer)?;
+        serializer.key_prefix.clear();
+        if let Some((threshold, extra)) = &self.error_fields {
+            if record.level().is_at_least(*threshold) {
+                extra(record, &mut serializer)?;
+            }
+        }
+        let redacted = mem::take(&mut serializer.redacted);
+        let msg = self.errors_into_msg(record, &mut fields);
+
+        for (key, value) in &self.default_fields {
+            let key = self.output_key(key);
+            if !fields.iter().any(|f| f.key == key) {
+                fields.push(Field::new(key, "str", value.clone()));
+            }
+        }
+        let correlation_id_key = self.output_key(&self.correlation_id_key);
+        if self.auto_correlation_id && !fields.iter().any(|f| f.key == correlation_id_key) {
+            let id = if self.deterministic {
+                id::encode(0)
+            } else {
+                id::ulid(self.now())
+            };
+            fields.push(Field::new(correlation_id_key, "str", id));
+        }
+
+        for (key, derive) in &self.derived_fields {
+            if let Some(value) = derive(&FieldMap { fields: &fields }) {
+                fields.push(Field::new(self.output_key(key), "str", value));
+            }
+        }
+
+        let field_count = fields.len();
+        if let Some((limit, panic)) = self.strict_field_limit {
+            let oversized: Vec<&str> = fields
+                .iter()
+                .filter(|f| f.value.len() > limit)
+                .map(|f| f.key.as_str())
+                .collect();
+            if !oversized.is_empty() {
+                if panic {
+                    panic!(
+                        "field values over the limit of {} bytes: {}",
+                        limit,
+                        oversized.join(", ")
+                    );
+                }
+                let oversized = oversized.join(",");
+                fields.push(self.meta_field("_oversized_keys", "str", oversized));
+            }
+        }
+        if let Some(budget) = self.value_budget {
+            let truncated = truncate_to_budget(&mut fields, budget);
+            if truncated > 0 {
+                fields.push(self.meta_field("_truncated", "usize", truncated.to_string()));
+            }
+        }
+        if self.report_field_count {
+            fields.push(self.meta_field("_fields", "usize", field_count.to_string()));
+        }
+        if self.list_redacted_keys && !redacted.is_empty() {
+            fields.push(self.meta_field("_redacted_keys", "str", redacted.join(",")));
+        }
+
+        Ok((fields, msg))
+    }
+
+    fn write_fields(&self, io: &mut dyn io::Write, fields: &[Field]) -> Result<(), Error> {
+        let mut pad = 0;
+        for (i, field) in fields.iter().enumerate() {
+            let mut first = i == 0;
+            if let Some(separator) = &self.meta_separator {
+                if field.meta && i > 0 && !fields[i - 1].meta {
+                    io.write_all(separator.as_bytes())
+                        .map_err(|e| Error::new(ErrorContext::Field(field.key.clone()), e))?;
+                    first = true;
+                }
+            }
+            let column = self.column_width(&field.key);
+            let width = if self.right_align_numbers && column > 0 && looks_numeric(&field.value) {
+                self.write_right_aligned(io, field, first, pad, column)
+            } else {
+                write!(io, "{:pad$}", "", pad = pad)
+                    .and_then(|_| self.write_field(io, field, first))
+            }
+            .map_err(|e| Error::new(ErrorContext::Field(field.key.clone()), e))?;
+            pad = column.saturating_sub(width);
+        }
+        Ok(())
+    }
+
+    /// Writes a field padded on the left to the `column` width,
+    /// after `pad` spaces left over from the previous column.
+    fn write_right_aligned(
+        &self,
+        io: &mut dyn io::Write,
+        field: &Field,
+        first: bool,
+        pad: usize,
+        column: usize,
+    ) -> io::Result<usize> {
+        let mut rendered = Vec::new();
+        let width = self.write_field(&mut rendered, field, true)?;
+        if !first {
+            io.write_all(self.field_separator.as_bytes())?;
+        }
+        let align = column.saturating_sub(width);
+        write!(io, "{:pad$}", "", pad = pad + align)?;
+        io.write_all(&rendered)?;
+        Ok(width + align)
+    }
+
+    /// Writes the values of the `columns` fields separated by tabs,
+    /// leaving columns empty for fields the record doesn't have.
+    fn write_tsv(
+        &self,
+        io: &mut dyn io::Write,
+        columns: &[String],
+        fields: &[Field],
+    ) -> Result<(), Error> {
+        for (i, column) in columns.iter().enumerate() {
+            let value = fields
+                .iter()
+                .find(|f| f.key == *column)
+                .map_or("", |f| &f.value);
+            let mut escaped = String::with_capacity(value.len() + 1);
+            if i > 0 {
+                escaped.push('\t');
+            }
+            for ch in value.chars() {
+                match ch {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\t' => escaped.push_str("\\t"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\r' => escaped.push_str("\\r"),
+                    ch => escaped.push(ch),
+                }
+            }
+            io.write_all(escaped.as_bytes())
+                .map_err(|e| Error::new(ErrorContext::Field(column.clone()), e))?;
+        }
+        Ok(())
+    }
+
+    /// The width that the `columns` option pads `key`'s field to.
+    fn column_width(&self, key: &str) -> usize {
+        self.columns
+            .iter()
+            .find(|(column, _)| column == key)
+            .map_or(0, |(_, width)| *width)
+    }
+
+    /// Writes a field, returning how many characters wide it is (not
+    /// counting the separator before it).
+    fn write_field(&self, io: &mut dyn io::Write, field: &Field, first: bool) -> io::Result<usize> {
+        if !first {
+            io.write_all(self.field_separator.as_bytes())?;
+        }
+        let mut rendered = self.key_escape.apply(&field.key, false).into_owned();
+        if field.bare {
+            io.write_all(rendered.as_bytes())?;
+            return Ok(rendered.chars().count());
+        }
+        if self.type_suffix {
+            rendered.push(':');
+            rendered.push_str(field.ty);
+        }
+        match &self.assignment_style {
+            Some(style) => rendered.push_str(style(TypeClass::of(field.ty))),
+            None => rendered.push('='),
+        }
+        let force = self.force_quotes
+            || (self.quote_numeric_strings && field.ty == "str" && looks_numeric(&field.value))
+            || field.value.contains(|ch| self.field_separator.contains(ch))
+            || self
+                .force_quotes_when
+                .as_ref()
+                .is_some_and(|quote| quote(&field.key, &field.value));
+        let mut value = if self.collapse_crlf {
+            field.value.replace("\r\n", "\n").into()
+        } else {
+            Cow::from(&field.value)
+        };
+        if !self.char_replacements.is_empty()
+            && value.contains(|ch| self.char_replacements.contains_key(&ch))
+        {
+            let mut replaced = String::with_capacity(value.len());
+            for ch in value.chars() {
+                match self.char_replacements.get(&ch) {
+                    Some(replacement) => replaced.push_str(replacement),
+                    None => replaced.push(ch),
+                }
+            }
+            value = replaced.into();
+        }
+        let mut escaped = if self.minimal_quoting && self.value_escape == EscapeStyle::Quote {
+            if force || value.contains(['=', '"']) || value.contains(char::is_control) {
+                optionally_quote(&value, true)
+            } else {
+                Cow::from(&*value)
+            }
+        } else {
+            self.value_escape.apply(&value, force)
+        };
+        if self.escape_forward_slash && escaped.starts_with('"') && escaped.contains('/') {
+            escaped = escaped.replace('/', "\\/").into();
+        }
+        rendered.push_str(&escaped);
+        io.write_all(rendered.as_bytes())?;
+        Ok(rendered.chars().count())
+    }
+}
+
+/// Numbers the records of all drains for the `seq` field.
+static SEQ: AtomicU64 = AtomicU64::new(0);
 
-    /// Redact the value with the given function.
-    Redact(fn(&'_ dyn Value) -> Arguments),
+/// Looks up the machine's hostname without calling into libc.
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
 }
 
-struct Options {
-    prefix: fn(&mut dyn io::Write, &Record) -> slog::Result,
-    print_level: bool,
-    print_msg: bool,
-    print_tag: bool,
-    force_quotes: bool,
-    redactor: fn(&Key) -> Redaction,
+/// The 32-bit FNV-1a hash of `bytes`, which is stable across runs and
+/// platforms.
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    })
 }
 
-impl Default for Options {
-    fn default() -> Self {
-        Options {
-            prefix: default_prefix,
-            print_level: false,
-            print_msg: false,
-            print_tag: false,
-            force_quotes: false,
-            redactor: |_| Redaction::Plain,
+/// Compares `a` and `b` like strings, except that runs of ASCII
+/// digits are compared by their numeric value.
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let a_len = a.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(a.len());
+            let b_len = b.find(|ch: char| !ch.is_ascii_digit()).unwrap_or(b.len());
+            let a_num = a[..a_len].trim_start_matches('0');
+            let b_num = b[..b_len].trim_start_matches('0');
+            let order = a_num
+                .len()
+                .cmp(&b_num.len())
+                .then_with(|| a_num.cmp(b_num))
+                .then_with(|| a_len.cmp(&b_len));
+            if order.is_ne() {
+                return order;
+            }
+            a = &a[a_len..];
+            b = &b[b_len..];
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            a = &a[x.len_utf8()..];
+            b = &b[y.len_utf8()..];
         }
     }
 }
 
-/// A drain & formatter for [logfmt](https://brandur.org/logfmt)-formatted messages.
-///
-/// # Format
-/// The default format looks like the somewhat-more-human-readable
-/// format in https://brandur.org/logfmt#human. You can customize it
-/// with the [`LogfmtBuilder`] method `set_prefix`.
-pub struct Logfmt<W: io::Write> {
-    io: RefCell<W>,
-    options: Options,
+/// Replaces every run of whitespace in `text` with a single space,
+/// and removes it from the ends.
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
-impl<W: io::Write> Logfmt<W> {
-    #[allow(clippy::new_ret_no_self)]
-    pub fn new(io: W) -> LogfmtBuilder<W> {
-        LogfmtBuilder {
-            io,
-            options: Default::default(),
+/// Returns whether `value` would be read as a number, like `007`,
+/// `-1` or `3.14e2`.
+fn looks_numeric(value: &str) -> bool {
+    value.bytes().any(|b| b.is_ascii_digit()) && value.parse::<f64>().is_ok()
+}
+
+/// Truncates the longest field values until the sum of all value
+/// lengths fits into `budget` bytes, returning how many values had
+/// to be cut. Built-in fields are left out, and don't count against
+/// the budget.
+///
+/// This finds the largest length cap that makes everything fit, so
+/// shorter values are left alone for as long as possible.
+fn truncate_to_budget(fields: &mut [Field], budget: usize) -> usize {
+    let mut fields: Vec<&mut Field> = fields.iter_mut().filter(|f| !f.meta).collect();
+    let total: usize = fields.iter().map(|f| f.value.len()).sum();
+    if total <= budget {
+        return 0;
+    }
+    let mut lens: Vec<usize> = fields.iter().map(|f| f.value.len()).collect();
+    lens.sort_unstable();
+    let mut cap = 0;
+    let mut below = 0;
+    for (i, len) in lens.iter().enumerate() {
+        let remaining = lens.len() - i;
+        let candidate = (budget - below) / remaining;
+        if candidate < *len {
+            cap = candidate;
+            break;
+        }
+        below += len;
+    }
+
+    let mut truncated = 0;
+    for field in fields.iter_mut().filter(|f| f.value.len() > cap) {
+        let mut end = cap;
+        while !field.value.is_char_boundary(end) {
+            end -= 1;
         }
+        field.value.truncate(end);
+        truncated += 1;
     }
+    truncated
 }
 
-/// A constructor for a [`Logfmt`] drain.
-pub struct LogfmtBuilder<W: io::Write> {
-    io: W,
-    options: Options,
+/// A key/value pair that survived redaction, with its value
+/// rendered but not yet quoted.
+struct Field {
+    key: String,
+    value: String,
+    /// The name of the type that the value was emitted as, like `i64` or `str`.
+    ty: &'static str,
+    /// Whether to write only the key, as a flag.
+    bare: bool,
+    /// Whether this is one of the built-in fields that describe the
+    /// record, rather than one logged by the application.
+    meta: bool,
 }
 
-impl<W: io::Write> LogfmtBuilder<W> {
-    /// Constructs the drain.
-    pub fn build(self) -> Logfmt<W> {
-        Logfmt {
-            io: RefCell::new(self.io),
-            options: self.options,
+impl Field {
+    fn new(key: impl Into<String>, ty: &'static str, value: impl Into<String>) -> Self {
+        Field {
+            key: key.into(),
+            value: value.into(),
+            ty,
+            bare: false,
+            meta: false,
         }
     }
 
-    /// Set a function that prints a (not necessarily
-    /// logfmt-formatted) prefix to the output stream.
-    pub fn set_prefix(mut self, prefix: fn(&mut dyn io::Write, &Record) -> slog::Result) -> Self {
-        self.options.prefix = prefix;
-        self
+    fn meta(key: impl Into<String>, ty: &'static str, value: impl Into<String>) -> Self {
+        Field {
+            meta: true,
+            ..Field::new(key, ty, value)
+        }
     }
+}
 
-    /// Sets the logger up to print no prefix, effectively starting the line entirely
-    /// logfmt field-formatted.
-    pub fn no_prefix(mut self) -> Self {
-        self.options.prefix = |_, _| Ok(());
-        self
+/// A read-only view of the fields collected for a record, see
+/// [`derived_field`](LogfmtBuilder::derived_field).
+pub struct FieldMap<'a> {
+    fields: &'a [Field],
+}
+
+impl<'a> FieldMap<'a> {
+    /// Returns the rendered value of the first field with `key`.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|f| f.key == key)
+            .map(|f| f.value.as_str())
     }
 
-    /// Sets a function that makes decisions on whether to log a field.
-    ///
-    /// This function must return a [`Redaction`] result, which has
-    /// two variants at the moment: `Redact::Skip` to not log the
-    /// field, and `Redact::Plain` to log the field value in plain
-    /// text.
-    pub fn redact(mut self, redact: fn(&Key) -> Redaction) -> Self {
-        self.options.redactor = redact;
-        self
+    /// Iterates over the keys and rendered values of the fields, in
+    /// the order they are written in.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.fields
+            .iter()
+            .map(|f| (f.key.as_str(), f.value.as_str()))
     }
 
-    /// Choose whether to print the log message.
-    ///
-    /// The default prefix already prints it, so the default is to skip.
-    pub fn print_msg(mut self, print: bool) -> Self {
-        self.options.print_msg = print;
-        self
+    /// Returns how many fields there are.
+    pub fn len(&self) -> usize {
+        self.fields.len()
     }
 
-    /// Choose whether to print the log level.
-    ///
-    /// The default prefix already prints it, so the default is to skip.
-    pub fn print_level(mut self, print: bool) -> Self {
-        self.options.print_level = print;
-        self
+    /// Returns whether there are no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
     }
+}
 
-    /// Choose whether to print the log level.
-    ///
-    /// The default prefix already prints it, so the default is to skip.
-    pub fn print_tag(mut self, print: bool) -> Self {
-        self.options.print_tag = print;
-        self
+/// Collects the fields of a record, so they can be post-processed
+/// as a whole before being written out.
+struct FieldCollector<'a> {
+    fields: &'a mut Vec<Field>,
+    options: &'a Options,
+    level: Option<Level>,
+    /// The keys that the redactor skipped or replaced, in order.
+    redacted: Vec<String>,
+    /// Prepended to the keys of fields, for `tag_as_key_prefix`.
+    key_prefix: String,
+}
+
+impl<'a> FieldCollector<'a> {
+    fn gated(&self, key: &Key) -> bool {
+        match (&self.options.level_gate, self.level) {
+            (Some(gate), Some(level)) => !gate(key, level),
+            _ => false,
+        }
     }
 
-    /// Force quoting field values even if they don't contain quotable characters.
-    ///
-    /// Setting this option will surround values with quotes like `foo="bar"`.
-    pub fn force_quotes(mut self) -> Self {
-        self.options.force_quotes = true;
-        self
+    /// Applies the [`EmptyKeyPolicy`], returning whether to skip the
+    /// field.
+    fn skip_empty_key(&self, key: &Key) -> Result<bool, slog::Error> {
+        if !key.is_empty() {
+            return Ok(false);
+        }
+        match self.options.empty_key {
+            EmptyKeyPolicy::Skip => Ok(true),
+            EmptyKeyPolicy::Placeholder(_) => Ok(false),
+            EmptyKeyPolicy::Error => Err(slog::Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "field with an empty key",
+            ))),
+        }
+    }
+
+    /// Formats a value, isolating panics in its `Display` or `Debug`
+    /// implementation if the drain is configured to.
+    fn render(&self, format: impl FnOnce() -> String) -> String {
+        if self.options.catch_value_panics {
+            panic::catch_unwind(AssertUnwindSafe(format))
+                .unwrap_or_else(|_| "<panicked>".to_string())
+        } else {
+            format()
+        }
+    }
+
+    fn note_redacted(&mut self, key: &str) {
+        if !self.redacted.iter().any(|k| *k == *key) {
+            self.redacted.push(key.to_string());
+        }
+    }
+
+    /// Applies the [value redactor](LogfmtBuilder::redact_values) to
+    /// a rendered value.
+    fn redact_value(&mut self, key: &str, rendered: String) -> String {
+        let redacted = match &self.options.value_redactor {
+            Some(redact) => redact(&rendered).map(Cow::into_owned),
+            None => None,
+        };
+        match redacted {
+            Some(redacted) => {
+                self.note_redacted(key);
+                redacted
+            }
+            None => rendered,
+        }
+    }
+
+    /// The key to write a field under, before it is shortened.
+    fn prefixed_key(&self, key: &str) -> String {
+        let key = self.options.field_key(key);
+        if self.key_prefix.is_empty() {
+            key
+        } else {
+            format!("{}{}", self.key_prefix, key)
+        }
     }
-}
 
-fn default_prefix(io: &mut dyn io::Write, rec: &Record) -> slog::Result {
-    let tag_prefix = if rec.tag() == "" { "" } else { "#" };
-    let tag_suffix = if rec.tag() == "" { "" } else { "\t" };
-    write!(
-        io,
-        "{level} | {tag_prefix}{tag}{tag_suffix}{msg}\t",
-        tag_prefix = tag_prefix,
-        tag = rec.tag(),
-        tag_suffix = tag_suffix,
-        level = rec.level().as_short_str(),
-        msg = rec.msg()
-    )?;
-    Ok(())
+    fn push(&mut self, key: &Key, ty: &'static str, value: String) {
+        let key = self.options.limit_key(self.prefixed_key(key));
+        self.fields.push(Field::new(key, ty, value));
+    }
 }
 
-struct LogfmtSerializer<'a, W: io::Write> {
+/// A [`slog::Serializer`] writing key/value pairs as logfmt fields
+/// to an [`io::Write`], using the options of a [`Logfmt`] drain.
+///
+/// This is useful for custom drains that want to emit some logfmt
+/// fields themselves. Since `serialize` calls can be spread over
+/// several passes (and several serializers), the state of whether a
+/// separator is needed before the next field can be inspected with
+/// [`is_first`](LogfmtSerializer::is_first) and carried over with
+/// [`mark_not_first`](LogfmtSerializer::mark_not_first).
+///
+/// Fields are written as they are emitted, so options that need to
+/// see the whole record (like
+/// [`value_budget`](LogfmtBuilder::value_budget)) don't apply, and
+/// neither does [`level_gated_keys`](LogfmtBuilder::level_gated_keys).
+pub struct LogfmtSerializer<'a, W: io::Write> {
     io: &'a mut W,
+    options: &'a Options,
     first: bool,
-    force_quotes: bool,
-    redactor: fn(&Key) -> Redaction,
+    scratch: Vec<Field>,
 }
 
 impl<'a, W: io::Write> LogfmtSerializer<'a, W> {
-    fn next_field(&mut self) -> Result<(), io::Error> {
-        if self.first {
+    /// Returns `true` if no field has been written by this serializer
+    /// yet (and it wasn't marked otherwise).
+    pub fn is_first(&self) -> bool {
+        self.first
+    }
+
+    /// Makes the next field start with a separator, as if a field had
+    /// already been written.
+    pub fn mark_not_first(&mut self) {
+        self.first = false;
+    }
+
+    fn write_collected(&mut self) -> slog::Result {
+        for field in self.scratch.drain(..) {
+            self.options.write_field(self.io, &field, self.first)?;
             self.first = false;
-        } else {
-            write!(self.io, " ")?;
         }
         Ok(())
     }
 }
 
+macro_rules! forward(
+    ($($f:ident($($arg:ident: $t:ty),*);)*) => {
+        $(
+            fn $f(&mut self, key: slog::Key $(, $arg: $t)*) -> slog::Result {
+                FieldCollector {
+                    fields: &mut self.scratch,
+                    options: self.options,
+                    level: None,
+                    redacted: Vec::new(),
+                    key_prefix: String::new(),
+                }
+                .$f(key $(, $arg)*)?;
+                self.write_collected()
+            }
+        )*
+    };
+);
+
+impl<'a, W: io::Write> slog::Serializer for LogfmtSerializer<'a, W> {
+    forward! {
+        emit_usize(val: usize);
+        emit_isize(val: isize);
+        emit_bool(val: bool);
+        emit_char(val: char);
+        emit_u8(val: u8);
+        emit_i8(val: i8);
+        emit_u16(val: u16);
+        emit_i16(val: i16);
+        emit_u32(val: u32);
+        emit_i32(val: i32);
+        emit_f32(val: f32);
+        emit_u64(val: u64);
+        emit_i64(val: i64);
+        emit_f64(val: f64);
+        emit_u128(val: u128);
+        emit_i128(val: i128);
+        emit_str(val: &str);
+        emit_unit();
+        emit_none();
+        emit_arguments(val: &Arguments);
+        emit_bytes(val: &[u8], kind: BytesKind);
+        emit_error(error: &(dyn std::error::Error + 'static));
+    }
+
+    #[cfg(feature = "nested-values")]
+    forward! {
+        emit_serde(val: &dyn slog::SerdeValue);
+    }
+}
+
 macro_rules! w(
-    ($s:expr, $k:expr, $v:expr) => {{
+    ($s:expr, $k:expr, $v:expr, $ty:expr) => {{
         use Redaction::*;
 
-        let redact = $s.redactor;
+        if $s.gated(&$k) || $s.skip_empty_key(&$k)? {
+            return Ok(());
+        }
         let val = $v;
-        match redact(&$k) {
-            Skip => {return Ok(());}
+        match $s.options.redaction(&$k) {
+            Skip => {
+                $s.note_redacted(&$k);
+                Ok(())
+            }
             Plain => {
-                $s.next_field()?;
-                write!($s.io, "{}=", $k)?;
-                write!($s.io, "{}", optionally_quote(&format!("{}", &val), $s.force_quotes))?;
+                let rendered = $s.render(|| format!("{}", &val));
+                let rendered = $s.redact_value(&$k, rendered);
+                $s.push(&$k, $ty, rendered);
                 Ok(())
             },
             Redact(redactor) => {
-                $s.next_field()?;
-                let val = format!("{}", redactor(&val));
-                write!($s.io, "{}={}", $k, optionally_quote(&val, $s.force_quotes))?;
+                $s.note_redacted(&$k);
+                let rendered = $s.render(|| format!("{}", redactor(&val)));
+                $s.push(&$k, $ty, rendered);
+                Ok(())
+            }
+            Mark(marker) => {
+                $s.note_redacted(&$k);
+                $s.push(&$k, $ty, marker.to_string());
+                Ok(())
+            }
+            LengthOnly => {
+                $s.note_redacted(&$k);
+                let rendered = $s.render(|| format!("{}", &val));
+                $s.push(&$k, $ty, format!("<{} bytes>", rendered.len()));
                 Ok(())
             }
         }
     }};
 );
 
+/// Appends `s` to `out` as a quoted JSON string.
+pub(crate) fn write_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+}
+
+/// Renders bytes that aren't meant as text the way slog does by
+/// default: uppercase hex, with `BytesKind::Value` getting a `0x`
+/// prefix and every two bytes separated by underscores.
+fn hex_bytes(bytes: &[u8], kind: BytesKind) -> String {
+    let grouped = matches!(kind, BytesKind::Value);
+    let mut out = String::with_capacity(bytes.len() * 3);
+    if grouped {
+        out.push_str("0x");
+    }
+    for (i, byte) in bytes.iter().enumerate() {
+        if grouped && i > 0 && i % 2 == 0 {
+            out.push('_');
+        }
+        out.push_str(&format!("{:02X}", byte));
+    }
+    out
+}
+
 fn can_skip_quoting(ch: char) -> bool {
-    ('a'..='z').contains(&ch)
-        || ('A'..='Z').contains(&ch)
-        || ('0'..='9').contains(&ch)
+    ch.is_ascii_lowercase()
+        || ch.is_ascii_uppercase()
+        || ch.is_ascii_digit()
         || ch == '-'
         || ch == '.'
         || ch == '_'
@@ -225,7 +3366,64 @@ fn can_skip_quoting(ch: char) -> bool {
         || ch == '+'
 }
 
-fn optionally_quote(input: &str, force: bool) -> Cow<str> {
+/// How keys or values are made safe to put into a logfmt line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeStyle {
+    /// Write the text as-is, except for carriage returns, which are
+    /// written as `\r` so they can't move a terminal's cursor.
+    Raw,
+
+    /// Surround the text with double quotes, escaping quotes,
+    /// backslashes and control characters inside, if it contains
+    /// anything other than letters, digits and `-._/@^+`.
+    Quote,
+
+    /// Replace every character other than letters, digits and
+    /// `-._/@^+` with an underscore.
+    Sanitize,
+
+    /// Percent-encode every byte other than the URL-safe letters,
+    /// digits and `-._~`, like `a%20b%25`, for consumers that don't
+    /// handle quoted values.
+    PercentEncode,
+}
+
+impl EscapeStyle {
+    /// Escapes `input` according to this style. `force` makes the
+    /// `Quote` style always quote.
+    fn apply(self, input: &str, force: bool) -> Cow<'_, str> {
+        match self {
+            EscapeStyle::Raw if input.contains('\r') => input.replace('\r', "\\r").into(),
+            EscapeStyle::Raw => input.into(),
+            EscapeStyle::Quote => optionally_quote(input, force),
+            EscapeStyle::PercentEncode => percent_encode(input),
+            EscapeStyle::Sanitize if input.chars().all(can_skip_quoting) => input.into(),
+            EscapeStyle::Sanitize => input
+                .chars()
+                .map(|ch| if can_skip_quoting(ch) { ch } else { '_' })
+                .collect::<String>()
+                .into(),
+        }
+    }
+}
+
+fn percent_encode(input: &str) -> Cow<'_, str> {
+    let unreserved = |b: u8| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+    if input.bytes().all(unreserved) {
+        return input.into();
+    }
+    let mut encoded = String::with_capacity(input.len() * 3);
+    for byte in input.bytes() {
+        if unreserved(byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded.into()
+}
+
+fn optionally_quote(input: &str, force: bool) -> Cow<'_, str> {
     if !force && input.chars().all(can_skip_quoting) {
         input.into()
     } else {
@@ -233,136 +3431,417 @@ fn optionally_quote(input: &str, force: bool) -> Cow<str> {
     }
 }
 
-impl<'a, W> slog::Serializer for LogfmtSerializer<'a, W>
-where
-    W: io::Write,
-{
-    fn emit_usize(&mut self, key: slog::Key, val: usize) -> Result<(), Error> {
-        w!(self, key, val)
+impl<'a> FieldCollector<'a> {
+    fn push_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        w!(self, key, val, "bool")
+    }
+}
+
+impl<'a> slog::Serializer for FieldCollector<'a> {
+    fn emit_usize(&mut self, key: slog::Key, val: usize) -> slog::Result {
+        w!(self, key, val, "usize")
+    }
+
+    fn emit_isize(&mut self, key: slog::Key, val: isize) -> slog::Result {
+        w!(self, key, val, "isize")
+    }
+
+    fn emit_bool(&mut self, key: slog::Key, val: bool) -> slog::Result {
+        if self.options.bool_style == BoolStyle::Value {
+            return self.push_bool(key, val);
+        }
+        if !val {
+            return Ok(());
+        }
+        let before = self.fields.len();
+        self.push_bool(key, val)?;
+        if self.fields.len() > before {
+            let field = self.fields.last_mut().expect("a field was just pushed");
+            // Redacted values are still logged as `key=value`.
+            field.bare = field.value == "true";
+        }
+        Ok(())
+    }
+
+    fn emit_char(&mut self, key: slog::Key, val: char) -> slog::Result {
+        w!(self, key, val, "char")
+    }
+
+    fn emit_u8(&mut self, key: slog::Key, val: u8) -> slog::Result {
+        w!(self, key, val, "u8")
     }
 
-    fn emit_isize(&mut self, key: slog::Key, val: isize) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_i8(&mut self, key: slog::Key, val: i8) -> slog::Result {
+        w!(self, key, val, "i8")
     }
 
-    fn emit_bool(&mut self, key: slog::Key, val: bool) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_u16(&mut self, key: slog::Key, val: u16) -> slog::Result {
+        w!(self, key, val, "u16")
     }
 
-    fn emit_char(&mut self, key: slog::Key, val: char) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_i16(&mut self, key: slog::Key, val: i16) -> slog::Result {
+        w!(self, key, val, "i16")
     }
 
-    fn emit_u8(&mut self, key: slog::Key, val: u8) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_u32(&mut self, key: slog::Key, val: u32) -> slog::Result {
+        w!(self, key, val, "u32")
     }
 
-    fn emit_i8(&mut self, key: slog::Key, val: i8) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_i32(&mut self, key: slog::Key, val: i32) -> slog::Result {
+        w!(self, key, val, "i32")
     }
 
-    fn emit_u16(&mut self, key: slog::Key, val: u16) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_f32(&mut self, key: slog::Key, val: f32) -> slog::Result {
+        w!(self, key, self.options.format_float(val), "f32")
     }
 
-    fn emit_i16(&mut self, key: slog::Key, val: i16) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_u64(&mut self, key: slog::Key, val: u64) -> slog::Result {
+        w!(self, key, val, "u64")
     }
 
-    fn emit_u32(&mut self, key: slog::Key, val: u32) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_i64(&mut self, key: slog::Key, val: i64) -> slog::Result {
+        w!(self, key, val, "i64")
     }
 
-    fn emit_i32(&mut self, key: slog::Key, val: i32) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_f64(&mut self, key: slog::Key, val: f64) -> slog::Result {
+        w!(self, key, self.options.format_float(val), "f64")
     }
 
-    fn emit_f32(&mut self, key: slog::Key, val: f32) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_u128(&mut self, key: slog::Key, val: u128) -> slog::Result {
+        w!(self, key, val, "u128")
     }
 
-    fn emit_u64(&mut self, key: slog::Key, val: u64) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_i128(&mut self, key: slog::Key, val: i128) -> slog::Result {
+        w!(self, key, val, "i128")
     }
 
-    fn emit_i64(&mut self, key: slog::Key, val: i64) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_str(&mut self, key: slog::Key, val: &str) -> slog::Result {
+        w!(self, key, &val, "str")
     }
 
-    fn emit_f64(&mut self, key: slog::Key, val: f64) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_unit(&mut self, key: slog::Key) -> slog::Result {
+        w!(self, key, "()", "unit")
     }
 
-    fn emit_u128(&mut self, key: slog::Key, val: u128) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_none(&mut self, key: slog::Key) -> slog::Result {
+        w!(self, key, "None", "none")
     }
 
-    fn emit_i128(&mut self, key: slog::Key, val: i128) -> Result<(), Error> {
-        w!(self, key, val)
+    fn emit_arguments<'b>(&mut self, key: slog::Key, val: &Arguments<'b>) -> slog::Result {
+        w!(self, key, val, "fmt")
     }
 
-    fn emit_str(&mut self, key: slog::Key, val: &str) -> Result<(), Error> {
-        w!(self, key, &val)
+    fn emit_bytes(&mut self, key: slog::Key, bytes: &[u8], kind: BytesKind) -> slog::Result {
+        let val = match kind {
+            BytesKind::Stream => self.options.invalid_utf8.decode(bytes)?.into_owned(),
+            _ => hex_bytes(bytes, kind),
+        };
+        w!(self, key, &*val, "bytes")
+    }
+
+    fn emit_error(
+        &mut self,
+        key: slog::Key,
+        error: &(dyn std::error::Error + 'static),
+    ) -> slog::Result {
+        let rendered = match self.options.error_style {
+            ErrorStyle::Display => error.to_string(),
+            ErrorStyle::DisplayChain => {
+                let mut chain = error.to_string();
+                let mut source = error.source();
+                while let Some(cause) = source {
+                    chain.push_str(": ");
+                    chain.push_str(&cause.to_string());
+                    source = cause.source();
+                }
+                chain
+            }
+            ErrorStyle::Debug => format!("{:?}", error),
+        };
+        w!(self, key, rendered, "error")
     }
 
-    fn emit_unit(&mut self, key: slog::Key) -> Result<(), Error> {
-        w!(self, key, "()")
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: slog::Key, val: &dyn slog::SerdeValue) -> slog::Result {
+        use Redaction::*;
+
+        if self.gated(&key) || self.skip_empty_key(&key)? {
+            return Ok(());
+        }
+        let redaction = self.options.redaction(key);
+        match redaction {
+            Plain | LengthOnly => {}
+            Skip => {
+                self.note_redacted(key);
+                return Ok(());
+            }
+            Mark(marker) => {
+                self.note_redacted(key);
+                self.push(&key, "json", marker.to_string());
+                return Ok(());
+            }
+            Redact(redactor) => {
+                self.note_redacted(key);
+                let rendered = self.render(|| format!("{}", redactor(val)));
+                self.push(&key, "json", rendered);
+                return Ok(());
+            }
+        }
+
+        let node = if self.options.catch_value_panics {
+            match panic::catch_unwind(AssertUnwindSafe(|| {
+                nested::Node::from_serialize(val.as_serde())
+            })) {
+                Ok(node) => node,
+                Err(_) => {
+                    self.push(&key, "json", "<panicked>".to_string());
+                    return Ok(());
+                }
+            }
+        } else {
+            nested::Node::from_serialize(val.as_serde())
+        }
+        .map_err(|e| slog::Error::Io(io::Error::other(e)))?;
+        if let LengthOnly = redaction {
+            self.note_redacted(key);
+            let length = node.to_text().len();
+            self.push(&key, "json", format!("<{} bytes>", length));
+            return Ok(());
+        }
+        let mut path = self.prefixed_key(key);
+        let options = self.options;
+        node.flatten(
+            &mut path,
+            &options.nest_separator,
+            0,
+            options.max_nest_depth,
+            &mut |entry| {
+                let redaction = entry.name.map_or(Plain, |name| options.redaction(name));
+                let value = match redaction {
+                    Plain if !entry.leaf => return false,
+                    Plain => {
+                        if options.skip_empty_nested && entry.node.is_empty_container() {
+                            return true;
+                        }
+                        self.redact_value(entry.path, entry.node.to_text())
+                    }
+                    Skip => {
+                        self.note_redacted(entry.path);
+                        return true;
+                    }
+                    Redact(redactor) => {
+                        self.note_redacted(entry.path);
+                        let text = entry.node.to_text();
+                        self.render(|| format!("{}", redactor(&text)))
+                    }
+                    Mark(marker) => {
+                        self.note_redacted(entry.path);
+                        marker.to_string()
+                    }
+                    LengthOnly => {
+                        self.note_redacted(entry.path);
+                        format!("<{} bytes>", entry.node.to_text().len())
+                    }
+                };
+                let key = options.limit_key(entry.path.to_string());
+                self.fields
+                    .push(Field::new(key, entry.node.type_name(), value));
+                true
+            },
+        );
+        Ok(())
     }
+}
 
-    fn emit_none(&mut self, key: slog::Key) -> Result<(), Error> {
-        w!(self, key, "None")
+impl<W: io::Write> Logfmt<W> {
+    /// Decides whether to log `record`, and if so, collects and
+    /// orders its fields.
+    fn prepare(
+        &self,
+        record: &Record,
+        logger_values: &OwnedKVList,
+    ) -> Result<Option<Prepared>, Error> {
+        if self.state.paused.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let exempt = self
+            .options
+            .rate_limit_exempt
+            .is_some_and(|level| record.level().is_at_least(level));
+        if let Some(per_second) = self.options.rate_limit {
+            if !exempt
+                && !self
+                    .state
+                    .rate_limiter
+                    .admit(per_second, self.options.now())
+            {
+                return Ok(None);
+            }
+        }
+        let rate = match self.sample(record) {
+            Some(rate) => rate,
+            None => return Ok(None),
+        };
+        let (mut fields, msg) = self
+            .options
+            .collect_fields(record, logger_values)
+            .map_err(|e| Error::new(ErrorContext::Serializing, e))?;
+        if self.options.sample_rate.is_some() {
+            fields.push(
+                self.options
+                    .meta_field("_sample_rate", "u64", rate.to_string()),
+            );
+        }
+        if self.options.rate_limit.is_some() {
+            let dropped = self.state.rate_limiter.take_dropped();
+            if dropped > 0 {
+                fields.push(
+                    self.options
+                        .meta_field("_rate_limited", "u64", dropped.to_string()),
+                );
+            }
+        }
+        if self.options.with_delta {
+            fields.push(
+                self.options
+                    .meta_field("delta_ms", "u128", self.delta_ms().to_string()),
+            );
+        }
+        if self.options.with_seq {
+            let seq = if self.options.deterministic {
+                &self.state.seq
+            } else {
+                &SEQ
+            };
+            let seq = seq.fetch_add(1, Ordering::Relaxed) + 1;
+            fields.push(self.options.meta_field("seq", "u64", seq.to_string()));
+        }
+        if self.options.with_elapsed {
+            let elapsed = match self.state.built {
+                Some(built) if !self.options.deterministic => built.elapsed().as_millis(),
+                _ => 0,
+            };
+            fields.push(
+                self.options
+                    .meta_field("elapsed_ms", "u128", elapsed.to_string()),
+            );
+        }
+        if !self.options.delta_keys.is_empty() {
+            self.add_value_deltas(&mut fields);
+        }
+        if let Some(depth) = &self.options.queue_depth {
+            let depth = depth.load(Ordering::Relaxed).to_string();
+            fields.push(self.options.meta_field("queue_depth", "usize", depth));
+        }
+        #[cfg(feature = "cpu-time")]
+        if self.options.with_cpu_time {
+            let cpu_time = if self.options.deterministic {
+                Some(Duration::ZERO)
+            } else {
+                cpu::process_cpu_time()
+            };
+            if let Some(cpu_time) = cpu_time {
+                let cpu_ms = cpu_time.as_millis().to_string();
+                fields.push(self.options.meta_field("cpu_ms", "u128", cpu_ms));
+            }
+        }
+        let high = match &self.high {
+            Some((threshold, high)) if record.level().is_at_least(*threshold) => Some(high),
+            _ => None,
+        };
+        if self.options.line_numbers {
+            let lines = match high {
+                Some(_) => &self.state.high_lines,
+                None => &self.state.lines,
+            };
+            let line = lines.fetch_add(1, Ordering::Relaxed) + 1;
+            fields.push(self.options.meta_field("line", "u64", line.to_string()));
+        }
+        if self.options.natural_sort {
+            fields.sort_by(|a, b| natural_cmp(&a.key, &b.key));
+        }
+        if !self.options.key_priority.is_empty() {
+            let priority = &self.options.key_priority;
+            fields.sort_by_key(|f| {
+                priority
+                    .get(&f.key)
+                    .copied()
+                    .unwrap_or(DEFAULT_KEY_PRIORITY)
+            });
+        }
+        // Grouping comes last, and keeps the order within each group.
+        if self.options.meta_separator.is_some() {
+            fields.sort_by_key(|f| f.meta);
+        }
+        Ok(Some(Prepared {
+            msg,
+            fields,
+            high: high.is_some(),
+        }))
     }
 
-    fn emit_arguments<'b>(&mut self, key: slog::Key, val: &Arguments<'b>) -> Result<(), Error> {
-        let val = format!("{}", val);
-        w!(self, key, &*val)
+    /// Writes a record whose fields were collected by `prepare`.
+    fn write_prepared(&self, record: &Record, prepared: Prepared) -> Result<(), Error> {
+        if self.options.schema_header && !self.state.header_written.swap(true, Ordering::Relaxed) {
+            let mut io = self.io.borrow_mut();
+            writeln!(io, "{}", self.schema_header())
+                .map_err(|e| Error::new(ErrorContext::Header, e))?;
+        }
+
+        let Prepared { msg, fields, high } = prepared;
+        let mut io = match &self.high {
+            Some((_, io)) if high => io.borrow_mut(),
+            _ => self.io.borrow_mut(),
+        };
+        let indent = self.state.indent.load(Ordering::Relaxed);
+        match &self.options.tsv {
+            Some(columns) => self.options.write_tsv(&mut *io, columns, &fields)?,
+            None => self.write_line(&mut *io, indent, record, msg.as_deref(), &fields)?,
+        }
+        io.write_all(b"\n")
+            .map_err(|e| Error::new(ErrorContext::Newline, e))?;
+        if let (Some(every), None) = (self.options.summary_every, &self.options.tsv) {
+            if let Some(summary) = self.state.summary.count(record.level(), every) {
+                write!(io, "{:indent$}", "", indent = indent)
+                    .map_err(|e| Error::new(ErrorContext::Prefix, e))?;
+                self.options.write_fields(&mut *io, &summary)?;
+                io.write_all(b"\n")
+                    .map_err(|e| Error::new(ErrorContext::Newline, e))?;
+            }
+        }
+        io.flush().map_err(|e| Error::new(ErrorContext::Flush, e))?;
+        if let Some(after_record) = &self.options.after_record {
+            after_record(record);
+        }
+
+        Ok(())
     }
 }
 
+/// A record that is going to be written; see [`Logfmt::prepare`].
+struct Prepared {
+    /// The message, if it was changed from the record's.
+    msg: Option<String>,
+    fields: Vec<Field>,
+    /// Whether the record goes to the split writer.
+    high: bool,
+}
+
 impl<W> slog::Drain for Logfmt<W>
 where
     W: io::Write,
 {
     type Ok = ();
-    type Err = io::Error;
+    type Err = Error;
 
     fn log<'a>(
         &self,
         record: &Record<'a>,
         logger_values: &OwnedKVList,
     ) -> Result<Self::Ok, Self::Err> {
-        let mut io = self.io.borrow_mut();
-        let prefix = self.options.prefix;
-        prefix(&mut *io, record)?;
-
-        let mut serializer = LogfmtSerializer {
-            io: &mut *io,
-            first: true,
-            force_quotes: self.options.force_quotes,
-            redactor: self.options.redactor,
-        };
-        if self.options.print_level {
-            let lvl = o!("level" => record.level().as_short_str());
-            lvl.serialize(record, &mut serializer)?;
-        }
-        if self.options.print_msg {
-            record.msg().serialize(
-                record,
-                #[allow(clippy::useless_conversion)] // necessary for dynamic-keys
-                "msg".into(),
-                &mut serializer,
-            )?;
-        }
-        if self.options.print_tag {
-            let tag = o!("level" => record.tag());
-            tag.serialize(record, &mut serializer)?;
+        match self.prepare(record, logger_values)? {
+            Some(prepared) => self.write_prepared(record, prepared),
+            None => Ok(()),
         }
-        logger_values.serialize(record, &mut serializer)?;
-        record.kv().serialize(record, &mut serializer)?;
-
-        io.write_all(b"\n")?;
-        io.flush()?;
-
-        Ok(())
     }
 }
@@ -0,0 +1,489 @@
+//! Flattening nested (serde) values into dotted logfmt keys.
+//!
+//! A value logged via slog's `emit_serde` is first turned into a
+//! [`Node`] tree, which is then walked to produce one field per leaf,
+//! named by the path to it: `{"user": {"id": 5}}` logged as `req`
+//! turns into `req.user.id=5`.
+
+use crate::write_json_string;
+use serde::ser::{self, Serialize};
+use std::fmt::{self, Write};
+
+/// A serialized value, detached from its original type.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Node {
+    Null,
+    Bool(bool),
+    Int(i128),
+    UInt(u128),
+    Float(f64),
+    Str(String),
+    Seq(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    /// Converts any serializable value into a tree.
+    pub(crate) fn from_serialize<T: Serialize + ?Sized>(value: &T) -> Result<Node, NodeError> {
+        value.serialize(NodeSerializer)
+    }
+
+    /// The type name used for `type_suffix` annotations.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Node::Null => "none",
+            Node::Bool(_) => "bool",
+            Node::Int(_) => "i128",
+            Node::UInt(_) => "u128",
+            Node::Float(_) => "f64",
+            Node::Str(_) => "str",
+            Node::Seq(_) | Node::Map(_) => "json",
+        }
+    }
+
+    /// Whether this is a map or sequence without any entries.
+    pub(crate) fn is_empty_container(&self) -> bool {
+        match self {
+            Node::Seq(items) => items.is_empty(),
+            Node::Map(entries) => entries.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Renders a leaf value as text; containers are rendered as JSON.
+    pub(crate) fn to_text(&self) -> String {
+        match self {
+            Node::Null => "null".to_string(),
+            Node::Bool(b) => b.to_string(),
+            Node::Int(i) => i.to_string(),
+            Node::UInt(u) => u.to_string(),
+            Node::Float(f) => f.to_string(),
+            Node::Str(s) => s.clone(),
+            Node::Seq(_) | Node::Map(_) => {
+                let mut out = String::new();
+                self.write_json(&mut out)
+                    .expect("writing to a String can't fail");
+                out
+            }
+        }
+    }
+
+    fn write_json(&self, out: &mut String) -> fmt::Result {
+        match self {
+            Node::Str(s) => {
+                write_json_string(out, s);
+                Ok(())
+            }
+            Node::Float(f) if !f.is_finite() => out.write_str("null"),
+            Node::Seq(items) => {
+                out.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(',')?;
+                    }
+                    item.write_json(out)?;
+                }
+                out.write_char(']')
+            }
+            Node::Map(entries) => {
+                out.write_char('{')?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(',')?;
+                    }
+                    write_json_string(out, key);
+                    out.write_char(':')?;
+                    value.write_json(out)?;
+                }
+                out.write_char('}')
+            }
+            leaf => out.write_str(&leaf.to_text()),
+        }
+    }
+
+    /// Walks the tree, calling `visit` with every entry below the
+    /// root (the root itself counts as an entry only if it is a
+    /// leaf). Subtrees below `max_depth` path components (counted from
+    /// the root key) are visited as leaves, to be emitted as a single
+    /// JSON value. If `visit` returns `true` for a subtree, it has
+    /// been dealt with and isn't descended into.
+    pub(crate) fn flatten(
+        &self,
+        path: &mut String,
+        separator: &str,
+        depth: usize,
+        max_depth: Option<usize>,
+        visit: &mut dyn FnMut(Entry) -> bool,
+    ) {
+        if self.is_leaf() || max_depth.is_some_and(|max| depth >= max) {
+            visit(Entry {
+                path,
+                name: None,
+                node: self,
+                leaf: true,
+            });
+            return;
+        }
+        let children: Vec<(String, bool, &Node)> = match self {
+            Node::Map(entries) => entries.iter().map(|(k, v)| (k.clone(), true, v)).collect(),
+            Node::Seq(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), false, v))
+                .collect(),
+            _ => unreachable!("leaves are visited above"),
+        };
+        for (key, named, child) in children {
+            let len = path.len();
+            path.push_str(separator);
+            path.push_str(&key);
+            let leaf = child.is_leaf() || max_depth.is_some_and(|max| depth + 1 >= max);
+            let entry = Entry {
+                path,
+                name: if named { Some(&key) } else { None },
+                node: child,
+                leaf,
+            };
+            if !visit(entry) && !leaf {
+                child.flatten(path, separator, depth + 1, max_depth, visit);
+            }
+            path.truncate(len);
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        match self {
+            Node::Map(entries) => entries.is_empty(),
+            Node::Seq(items) => items.is_empty(),
+            _ => true,
+        }
+    }
+}
+
+/// A value found by [`Node::flatten`].
+pub(crate) struct Entry<'a> {
+    /// The dotted path to the value, starting with the root key.
+    pub(crate) path: &'a str,
+
+    /// The map key the value is stored under; `None` for sequence
+    /// items and the root.
+    pub(crate) name: Option<&'a str>,
+
+    pub(crate) node: &'a Node,
+
+    /// Whether the value is written as a single field rather than
+    /// flattened further.
+    pub(crate) leaf: bool,
+}
+
+/// The error type of [`NodeSerializer`]; only produced by the
+/// serialized value itself.
+#[derive(Debug)]
+pub(crate) struct NodeError(String);
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+impl ser::Error for NodeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        NodeError(msg.to_string())
+    }
+}
+
+struct NodeSerializer;
+
+/// Collects the elements of a sequence-like value.
+struct SeqBuilder {
+    variant: Option<&'static str>,
+    items: Vec<Node>,
+}
+
+/// Collects the entries of a map-like value.
+struct MapBuilder {
+    variant: Option<&'static str>,
+    entries: Vec<(String, Node)>,
+    next_key: Option<String>,
+}
+
+/// Wraps `node` in a single-entry map named by an enum variant.
+fn in_variant(variant: Option<&'static str>, node: Node) -> Node {
+    match variant {
+        Some(variant) => Node::Map(vec![(variant.to_string(), node)]),
+        None => node,
+    }
+}
+
+impl ser::Serializer for NodeSerializer {
+    type Ok = Node;
+    type Error = NodeError;
+    type SerializeSeq = SeqBuilder;
+    type SerializeTuple = SeqBuilder;
+    type SerializeTupleStruct = SeqBuilder;
+    type SerializeTupleVariant = SeqBuilder;
+    type SerializeMap = MapBuilder;
+    type SerializeStruct = MapBuilder;
+    type SerializeStructVariant = MapBuilder;
+
+    fn serialize_bool(self, v: bool) -> Result<Node, NodeError> {
+        Ok(Node::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Node, NodeError> {
+        Ok(Node::Int(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Node, NodeError> {
+        Ok(Node::Int(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Node, NodeError> {
+        Ok(Node::Int(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Node, NodeError> {
+        Ok(Node::Int(v.into()))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Node, NodeError> {
+        Ok(Node::Int(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Node, NodeError> {
+        Ok(Node::UInt(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Node, NodeError> {
+        Ok(Node::UInt(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Node, NodeError> {
+        Ok(Node::UInt(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Node, NodeError> {
+        Ok(Node::UInt(v.into()))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Node, NodeError> {
+        Ok(Node::UInt(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Node, NodeError> {
+        Ok(Node::Float(v.into()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Node, NodeError> {
+        Ok(Node::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Node, NodeError> {
+        Ok(Node::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Node, NodeError> {
+        Ok(Node::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Node, NodeError> {
+        Ok(Node::Seq(v.iter().map(|&b| Node::UInt(b.into())).collect()))
+    }
+
+    fn serialize_none(self) -> Result<Node, NodeError> {
+        Ok(Node::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Node, NodeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Node, NodeError> {
+        Ok(Node::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Node, NodeError> {
+        Ok(Node::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Node, NodeError> {
+        Ok(Node::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Node, NodeError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Node, NodeError> {
+        Ok(in_variant(Some(variant), value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqBuilder, NodeError> {
+        Ok(SeqBuilder {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqBuilder, NodeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqBuilder, NodeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqBuilder, NodeError> {
+        Ok(SeqBuilder {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapBuilder, NodeError> {
+        Ok(MapBuilder {
+            variant: None,
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapBuilder, NodeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapBuilder, NodeError> {
+        Ok(MapBuilder {
+            variant: Some(variant),
+            entries: Vec::with_capacity(len),
+            next_key: None,
+        })
+    }
+}
+
+impl SeqBuilder {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), NodeError> {
+        self.items.push(value.serialize(NodeSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Node, NodeError> {
+        Ok(in_variant(self.variant, Node::Seq(self.items)))
+    }
+}
+
+macro_rules! seq_impl(
+    ($($t:ident :: $f:ident),*) => {
+        $(
+            impl ser::$t for SeqBuilder {
+                type Ok = Node;
+                type Error = NodeError;
+
+                fn $f<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), NodeError> {
+                    self.push(value)
+                }
+
+                fn end(self) -> Result<Node, NodeError> {
+                    self.finish()
+                }
+            }
+        )*
+    };
+);
+
+seq_impl!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl MapBuilder {
+    fn finish(self) -> Result<Node, NodeError> {
+        Ok(in_variant(self.variant, Node::Map(self.entries)))
+    }
+}
+
+impl ser::SerializeMap for MapBuilder {
+    type Ok = Node;
+    type Error = NodeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), NodeError> {
+        self.next_key = Some(key.serialize(NodeSerializer)?.to_text());
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), NodeError> {
+        let key = self
+            .next_key
+            .take()
+            .ok_or_else(|| NodeError("map value without a key".to_string()))?;
+        self.entries.push((key, value.serialize(NodeSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Node, NodeError> {
+        self.finish()
+    }
+}
+
+macro_rules! struct_impl(
+    ($($t:ident),*) => {
+        $(
+            impl ser::$t for MapBuilder {
+                type Ok = Node;
+                type Error = NodeError;
+
+                fn serialize_field<T: Serialize + ?Sized>(
+                    &mut self,
+                    key: &'static str,
+                    value: &T,
+                ) -> Result<(), NodeError> {
+                    self.entries
+                        .push((key.to_string(), value.serialize(NodeSerializer)?));
+                    Ok(())
+                }
+
+                fn end(self) -> Result<Node, NodeError> {
+                    self.finish()
+                }
+            }
+        )*
+    };
+);
+
+struct_impl!(SerializeStruct, SerializeStructVariant);
@@ -0,0 +1,108 @@
+//! Keeping the most recent lines in memory, to dump after a crash.
+//!
+//! Wrapping a drain's writer in a [`RingBuffer`] passes every line
+//! on as usual, and also keeps the last few of them around. A handle
+//! from [`RingBuffer::retained`] can then write those out from
+//! anywhere, e.g. from a panic hook, to show what led up to it. To
+//! only keep lines in memory, wrap [`io::sink`].
+//!
+//! # Example
+//! ```rust
+//! use slog::{o, Drain, Logger};
+//! use slog_logfmt::ring::RingBuffer;
+//! use slog_logfmt::Logfmt;
+//! use std::io::{self, stderr};
+//! use std::sync::Mutex;
+//!
+//! let ring = RingBuffer::new(io::sink(), 100);
+//! let retained = ring.retained();
+//! std::panic::set_hook(Box::new(move |_| {
+//!     let _ = retained.dump(&mut stderr());
+//! }));
+//! let drain = Logfmt::new(ring).build();
+//! let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+//! ```
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// A writer that passes lines on to another writer and keeps the
+/// most recent `capacity` of them.
+pub struct RingBuffer<W: io::Write> {
+    inner: W,
+    partial: Vec<u8>,
+    retained: Retained,
+}
+
+impl<W: io::Write> RingBuffer<W> {
+    /// Wraps `inner`, keeping the last `capacity` lines written to it.
+    pub fn new(inner: W, capacity: usize) -> RingBuffer<W> {
+        RingBuffer {
+            inner,
+            partial: Vec::new(),
+            retained: Retained {
+                lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+                capacity,
+            },
+        }
+    }
+
+    /// Returns a handle to the kept lines, which can be sent to other
+    /// threads and dumps them while this writer keeps collecting.
+    pub fn retained(&self) -> Retained {
+        self.retained.clone()
+    }
+
+    /// Writes the kept lines to `out`, oldest first.
+    pub fn dump(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        self.retained.dump(out)
+    }
+}
+
+impl<W: io::Write> io::Write for RingBuffer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        let mut rest = &buf[..written];
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..=pos]);
+            self.retained.push(std::mem::take(&mut self.partial));
+            rest = &rest[pos + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The lines kept by a [`RingBuffer`].
+#[derive(Clone)]
+pub struct Retained {
+    lines: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    capacity: usize,
+}
+
+impl Retained {
+    fn push(&self, line: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// Writes the kept lines to `out`, oldest first.
+    pub fn dump(&self, out: &mut dyn io::Write) -> io::Result<()> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        for line in lines.iter() {
+            out.write_all(line)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,90 @@
+//! Routing records to different drains by level.
+//!
+//! A [`LevelSplit`] drain sends each record to every [`Logfmt`]
+//! drain whose level range includes the record's level, e.g. to keep
+//! an `error.log` next to the complete `app.log`.
+//!
+//! # Example
+//! ```rust,no_run
+//! use slog::{o, Drain, Level, Logger};
+//! use slog_logfmt::split::LevelSplit;
+//! use slog_logfmt::Logfmt;
+//! use std::fs::File;
+//! use std::sync::Mutex;
+//!
+//! let errors = Logfmt::new(File::create("error.log").unwrap()).build();
+//! let everything = Logfmt::new(File::create("app.log").unwrap()).build();
+//! let drain = LevelSplit::new()
+//!     .route(Level::Error, Level::Critical, errors)
+//!     .catch_all(everything);
+//! let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+//! ```
+
+use crate::{Error, Logfmt};
+use slog::{Drain, Level, OwnedKVList, Record};
+use std::io;
+
+/// A drain sending records to different [`Logfmt`] drains based on
+/// their level.
+///
+/// Like [`Logfmt`], this drain is not `Sync`, so you'll want to wrap
+/// it in `slog-async` or a `Mutex`.
+pub struct LevelSplit<W: io::Write> {
+    routes: Vec<(Level, Level, Logfmt<W>)>,
+    catch_all: Option<Logfmt<W>>,
+}
+
+impl<W: io::Write> LevelSplit<W> {
+    /// Creates a drain that doesn't route records anywhere yet.
+    pub fn new() -> Self {
+        LevelSplit {
+            routes: Vec::new(),
+            catch_all: None,
+        }
+    }
+
+    /// Send records from level `from` up to and including the more
+    /// severe level `to` to `drain`.
+    ///
+    /// Ranges may overlap, in which case records are logged to every
+    /// drain whose range includes them.
+    pub fn route(mut self, from: Level, to: Level, drain: Logfmt<W>) -> Self {
+        self.routes.push((from, to, drain));
+        self
+    }
+
+    /// Send every record to `drain`, in addition to the drains whose
+    /// range it falls into.
+    pub fn catch_all(mut self, drain: Logfmt<W>) -> Self {
+        self.catch_all = Some(drain);
+        self
+    }
+}
+
+impl<W: io::Write> Default for LevelSplit<W> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: io::Write> Drain for LevelSplit<W> {
+    type Ok = ();
+    type Err = Error;
+
+    fn log<'a>(
+        &self,
+        record: &Record<'a>,
+        logger_values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let level = record.level();
+        for (from, to, drain) in &self.routes {
+            if level.is_at_least(*from) && to.is_at_least(level) {
+                drain.log(record, logger_values)?;
+            }
+        }
+        if let Some(drain) = &self.catch_all {
+            drain.log(record, logger_values)?;
+        }
+        Ok(())
+    }
+}
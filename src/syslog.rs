@@ -0,0 +1,410 @@
+//! Shipping logfmt records to a syslog daemon.
+//!
+//! The [`Syslog`] drain formats each record with a regular
+//! [`Logfmt`] drain and sends the result as the MSG part of a syslog
+//! message, framed with an [RFC 5424](https://tools.ietf.org/html/rfc5424)
+//! or [RFC 3164](https://tools.ietf.org/html/rfc3164) header. The
+//! syslog severity is derived from the record's level. With RFC 5424,
+//! the fields can also be sent as STRUCTURED-DATA, see
+//! [`SyslogBuilder::structured_data`].
+//!
+//! # Example
+//! ```rust,no_run
+//! use slog::{info, o, Drain, Logger};
+//! use slog_logfmt::syslog::{Syslog, Transport};
+//! use slog_logfmt::Logfmt;
+//!
+//! let transport = Transport::unix("/dev/log").unwrap();
+//! let drain = Syslog::new(transport, Logfmt::new(Vec::new()).no_prefix())
+//!     .app_name("myapp")
+//!     .build()
+//!     .fuse();
+//! let drain = slog_async::Async::new(drain).build().fuse();
+//! let logger = Logger::root(drain, o!());
+//! info!(logger, "started"; "port" => 8080);
+//! ```
+
+use crate::time::Utc;
+use crate::{Field, LevelNumbering, Logfmt, LogfmtBuilder};
+use slog::{Level, OwnedKVList, Record};
+use std::io::{self, Write};
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The connection a [`Syslog`] drain sends messages over.
+pub enum Transport {
+    /// A local unix datagram socket, usually `/dev/log`.
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+
+    /// A (connected) UDP socket; each record is one datagram.
+    Udp(UdpSocket),
+
+    /// A TCP stream; records are terminated by a newline.
+    Tcp(TcpStream),
+}
+
+impl Transport {
+    /// Connects to a local syslog daemon's datagram socket.
+    #[cfg(unix)]
+    pub fn unix<P: AsRef<Path>>(path: P) -> io::Result<Transport> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Transport::Unix(socket))
+    }
+
+    /// Sends messages via UDP to the given syslog endpoint.
+    ///
+    /// If `addr` resolves to several addresses, the first one that a
+    /// socket can be connected to is used.
+    pub fn udp<A: ToSocketAddrs>(addr: A) -> io::Result<Transport> {
+        let mut last_error = None;
+        for addr in addr.to_socket_addrs()? {
+            let local: IpAddr = match addr {
+                SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            };
+            match UdpSocket::bind((local, 0)).and_then(|socket| {
+                socket.connect(addr)?;
+                Ok(socket)
+            }) {
+                Ok(socket) => return Ok(Transport::Udp(socket)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no addresses to send to")
+        }))
+    }
+
+    /// Sends messages via TCP to the given syslog endpoint.
+    pub fn tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Transport> {
+        Ok(Transport::Tcp(TcpStream::connect(addr)?))
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        match self {
+            #[cfg(unix)]
+            Transport::Unix(socket) => socket.send(message).map(|_| ()),
+            Transport::Udp(socket) => socket.send(message).map(|_| ()),
+            Transport::Tcp(stream) => {
+                let mut stream: &TcpStream = stream;
+                stream.write_all(message)?;
+                stream.write_all(b"\n")
+            }
+        }
+    }
+}
+
+/// The header format to frame messages with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The "BSD" syslog format: `<PRI>Mmm dd hh:mm:ss HOST APP[PID]: MSG`.
+    Rfc3164,
+
+    /// The IETF syslog format: `<PRI>1 TIMESTAMP HOST APP PID - - MSG`.
+    Rfc5424,
+}
+
+/// The syslog facility that messages are attributed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    AuthPriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+/// A drain sending logfmt-formatted records to syslog.
+///
+/// Like [`Logfmt`], this drain is not `Sync`, so you'll want to wrap
+/// it in `slog-async` or a `Mutex`.
+pub struct Syslog {
+    body: Logfmt<Vec<u8>>,
+    transport: Transport,
+    protocol: Protocol,
+    facility: Facility,
+    hostname: Option<String>,
+    app_name: String,
+    structured_data: Option<String>,
+    structured_data_only: bool,
+}
+
+impl Syslog {
+    /// Starts building a syslog drain sending over `transport`, using
+    /// `body` to format the message part of each record.
+    ///
+    /// Each record has to fit into a single line, so the body's
+    /// [`schema_header`](LogfmtBuilder::schema_header),
+    /// [`summary_every`](LogfmtBuilder::summary_every) and
+    /// [`vertical`](LogfmtBuilder::vertical) options are turned off.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(transport: Transport, body: LogfmtBuilder<Vec<u8>>) -> SyslogBuilder {
+        let app_name = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "-".to_string());
+        SyslogBuilder {
+            body,
+            transport,
+            protocol: Protocol::Rfc5424,
+            facility: Facility::User,
+            hostname: None,
+            app_name,
+            structured_data: None,
+            structured_data_only: false,
+        }
+    }
+
+    /// Writes the record's fields as an SD-ELEMENT named `id`, like
+    /// `[meta user="alice" path="/a\]"]`.
+    fn write_structured_data(
+        &self,
+        buf: &mut Vec<u8>,
+        id: &str,
+        fields: &[Field],
+    ) -> io::Result<()> {
+        write!(buf, "[{}", sd_name(id))?;
+        for field in fields {
+            write!(buf, " {}=\"", sd_name(&field.key))?;
+            for ch in field.value.chars() {
+                if matches!(ch, '"' | '\\' | ']') {
+                    buf.push(b'\\');
+                }
+                write!(buf, "{}", ch)?;
+            }
+            buf.push(b'"');
+        }
+        buf.push(b']');
+        Ok(())
+    }
+
+    fn write_header(&self, buf: &mut Vec<u8>, level: Level) -> io::Result<()> {
+        let pri = (self.facility as usize) * 8 + LevelNumbering::Syslog.number(level);
+        let now = Utc::from_system_time(SystemTime::now());
+        let pid = std::process::id();
+        match self.protocol {
+            Protocol::Rfc5424 => write!(
+                buf,
+                "<{pri}>1 {y:04}-{mo:02}-{d:02}T{h:02}:{mi:02}:{s:02}.{ms:03}Z {host} {app} {pid} - ",
+                pri = pri,
+                y = now.year,
+                mo = now.month,
+                d = now.day,
+                h = now.hour,
+                mi = now.minute,
+                s = now.second,
+                ms = now.nanos / 1_000_000,
+                host = self.hostname.as_deref().unwrap_or("-"),
+                app = self.app_name,
+                pid = pid,
+            ),
+            Protocol::Rfc3164 => {
+                write!(
+                    buf,
+                    "<{pri}>{mon} {d:>2} {h:02}:{mi:02}:{s:02} ",
+                    pri = pri,
+                    mon = now.month_abbrev(),
+                    d = now.day,
+                    h = now.hour,
+                    mi = now.minute,
+                    s = now.second,
+                )?;
+                if let Some(host) = &self.hostname {
+                    write!(buf, "{} ", host)?;
+                }
+                write!(buf, "{}[{}]: ", self.app_name, pid)
+            }
+        }
+    }
+}
+
+/// A constructor for a [`Syslog`] drain.
+pub struct SyslogBuilder {
+    body: LogfmtBuilder<Vec<u8>>,
+    transport: Transport,
+    protocol: Protocol,
+    facility: Facility,
+    hostname: Option<String>,
+    app_name: String,
+    structured_data: Option<String>,
+    structured_data_only: bool,
+}
+
+impl SyslogBuilder {
+    /// Constructs the drain.
+    pub fn build(self) -> Syslog {
+        let mut body = self.body;
+        body.options.schema_header = false;
+        body.options.summary_every = None;
+        body.options.vertical = false;
+        Syslog {
+            body: body.build(),
+            transport: self.transport,
+            protocol: self.protocol,
+            facility: self.facility,
+            hostname: self.hostname.map(|host| header_field(&host, 255)),
+            app_name: header_field(&self.app_name, 48),
+            structured_data: self.structured_data,
+            structured_data_only: self.structured_data_only,
+        }
+    }
+
+    /// Choose the header format. The default is RFC 5424.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Set the facility messages are logged under. The default is `User`.
+    pub fn facility(mut self, facility: Facility) -> Self {
+        self.facility = facility;
+        self
+    }
+
+    /// Set the HOSTNAME header field.
+    ///
+    /// Like the APP-NAME, it is cut to fit the header, see
+    /// [`app_name`](SyslogBuilder::app_name). By default, RFC 5424 messages carry the NILVALUE `-` and RFC
+    /// 3164 messages omit the hostname, leaving it to the daemon.
+    pub fn hostname(mut self, hostname: &str) -> Self {
+        self.hostname = Some(hostname.to_string());
+        self
+    }
+
+    /// Set the APP-NAME header field. Defaults to the executable's name.
+    ///
+    /// Spaces and characters other than printable ASCII are replaced
+    /// with `_`, and the name is cut to 48 characters. An empty name
+    /// is sent as `-`.
+    pub fn app_name(mut self, app_name: &str) -> Self {
+        self.app_name = app_name.to_string();
+        self
+    }
+
+    /// Also send each record's fields as an RFC 5424 STRUCTURED-DATA
+    /// element with the SD-ID `id`, like `[meta user="alice"]`, in
+    /// front of the logfmt MSG.
+    ///
+    /// The fields are collected with the body drain's options, so
+    /// redaction applies. Characters that aren't allowed in SD-NAMEs
+    /// are replaced with `_`, and names are cut to 32 characters.
+    /// Only applies to [`Protocol::Rfc5424`]; RFC 3164 has no
+    /// structured data.
+    pub fn structured_data(mut self, id: &str) -> Self {
+        self.structured_data = Some(id.to_string());
+        self.structured_data_only = false;
+        self
+    }
+
+    /// Like [`structured_data`](SyslogBuilder::structured_data), but
+    /// send only the record's message as the MSG instead of the whole
+    /// logfmt line.
+    pub fn structured_data_only(mut self, id: &str) -> Self {
+        self.structured_data = Some(id.to_string());
+        self.structured_data_only = true;
+        self
+    }
+}
+
+impl slog::Drain for Syslog {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log<'a>(
+        &self,
+        record: &Record<'a>,
+        logger_values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        let structured_data = match self.protocol {
+            Protocol::Rfc5424 => self.structured_data.as_deref(),
+            Protocol::Rfc3164 => None,
+        };
+        // Collect the fields once, so that the structured data and the
+        // MSG agree (and counters and callbacks only see one record).
+        let prepared = match self.body.prepare(record, logger_values)? {
+            Some(prepared) => prepared,
+            None => return Ok(()),
+        };
+        let mut sd = Vec::new();
+        if let Some(id) = structured_data {
+            self.write_structured_data(&mut sd, id, &prepared.fields)?;
+        }
+        let body = match structured_data {
+            Some(_) if self.structured_data_only => prepared
+                .msg
+                .unwrap_or_else(|| record.msg().to_string())
+                .into_bytes(),
+            _ => {
+                // Don't send leftovers of a record that failed to write.
+                self.body.io.borrow_mut().clear();
+                self.body.write_prepared(record, prepared)?;
+                mem::take(&mut *self.body.io.borrow_mut())
+            }
+        };
+        let body = body.strip_suffix(b"\n").unwrap_or(&body);
+
+        let mut message = Vec::with_capacity(body.len() + sd.len() + 64);
+        self.write_header(&mut message, record.level())?;
+        match structured_data {
+            Some(_) => message.extend_from_slice(&sd),
+            None if self.protocol == Protocol::Rfc5424 => message.push(b'-'),
+            None => {}
+        }
+        if self.protocol == Protocol::Rfc5424 {
+            message.push(b' ');
+        }
+        message.extend_from_slice(body);
+        self.transport.send(&message)
+    }
+}
+
+/// Makes `name` a valid RFC 5424 SD-NAME: at most 32 printable ASCII
+/// characters other than `=`, space, `]` and `"`.
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .take(32)
+        .map(|ch| match ch {
+            '=' | ' ' | ']' | '"' => '_',
+            ch if ch.is_ascii_graphic() => ch,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Makes `value` a valid RFC 5424 header field of at most `max`
+/// printable ASCII characters, or the NILVALUE `-` if it is empty.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .take(max)
+        .map(|ch| if ch.is_ascii_graphic() { ch } else { '_' })
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
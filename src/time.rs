@@ -0,0 +1,142 @@
+//! Minimal UTC calendar arithmetic, so we can render timestamps
+//! without pulling in a date/time crate.
+
+use std::fmt::{self, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How to render the `ts` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// RFC 3339 in UTC with second precision: `2020-03-12T08:00:00Z`.
+    Rfc3339,
+
+    /// RFC 3339 in UTC with millisecond precision: `2020-03-12T08:00:00.123Z`.
+    Rfc3339Millis,
+
+    /// Seconds since the unix epoch.
+    UnixSeconds,
+
+    /// Milliseconds since the unix epoch.
+    UnixMillis,
+
+    /// Nanoseconds since the unix epoch.
+    UnixNanos,
+
+    /// Milliseconds since the unix epoch in base 36 (`0-9a-z`),
+    /// padded to 9 digits: `0k7ogpabf`. Short, and still sorts in
+    /// time order as text, up to the year 5188. Times before the
+    /// epoch are written as `000000000`.
+    Base36Millis,
+}
+
+impl TimestampFormat {
+    /// Appends the formatted timestamp to `out`.
+    pub(crate) fn write(self, t: SystemTime, out: &mut String) -> fmt::Result {
+        match self {
+            TimestampFormat::Rfc3339 | TimestampFormat::Rfc3339Millis => {
+                let utc = Utc::from_system_time(t);
+                write!(
+                    out,
+                    "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                    utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second
+                )?;
+                if self == TimestampFormat::Rfc3339Millis {
+                    write!(out, ".{:03}", utc.nanos / 1_000_000)?;
+                }
+                out.write_char('Z')
+            }
+            TimestampFormat::UnixSeconds => {
+                write!(out, "{}", unix_nanos(t).div_euclid(1_000_000_000))
+            }
+            TimestampFormat::UnixMillis => write!(out, "{}", unix_nanos(t).div_euclid(1_000_000)),
+            TimestampFormat::UnixNanos => write!(out, "{}", unix_nanos(t)),
+            TimestampFormat::Base36Millis => {
+                const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+                let mut millis = unix_nanos(t).div_euclid(1_000_000).max(0);
+                let mut digits = Vec::with_capacity(9);
+                while millis > 0 || digits.len() < 9 {
+                    digits.push(DIGITS[(millis % 36) as usize] as char);
+                    millis /= 36;
+                }
+                digits
+                    .iter()
+                    .rev()
+                    .try_for_each(|&digit| out.write_char(digit))
+            }
+        }
+    }
+}
+
+/// Returns the (possibly negative) nanoseconds since the unix epoch.
+fn unix_nanos(t: SystemTime) -> i128 {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+/// A broken-down UTC timestamp.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Utc {
+    pub(crate) year: i64,
+    pub(crate) month: u32,
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+    pub(crate) nanos: u32,
+}
+
+impl Utc {
+    pub(crate) fn from_system_time(t: SystemTime) -> Utc {
+        let (secs, nanos) = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+            Err(e) => {
+                // Before the epoch: round down to the previous full second.
+                let d: Duration = e.duration();
+                let secs = -(d.as_secs() as i64);
+                match d.subsec_nanos() {
+                    0 => (secs, 0),
+                    n => (secs - 1, 1_000_000_000 - n),
+                }
+            }
+        };
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        Utc {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+            nanos,
+        }
+    }
+
+    /// The three-letter English month abbreviation, as used by RFC 3164.
+    #[cfg_attr(not(feature = "syslog"), allow(dead_code))]
+    pub(crate) fn month_abbrev(&self) -> &'static str {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        MONTHS[(self.month - 1) as usize]
+    }
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) triple.
+///
+/// This is Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,293 @@
+//! Wrappers for logging values that need special treatment.
+
+use slog::{BytesKind, Key, Record, Serializer, Value};
+use std::fmt;
+#[cfg(feature = "nested-values")]
+use std::fmt::{Arguments, Display};
+
+/// Logs a byte string as text.
+///
+/// Valid UTF-8 sequences are printed as-is; how any invalid bytes
+/// are rendered is decided by the drain's
+/// [`InvalidUtf8Policy`](crate::InvalidUtf8Policy). Other drains
+/// will see these bytes as a [`BytesKind::Stream`].
+///
+/// Note that drains which serialize values ahead of time, like
+/// `slog-async`, turn the bytes into slog's default hex
+/// representation before they reach this crate's drain.
+#[derive(Debug, Clone, Copy)]
+pub struct BytesValue<'a>(pub &'a [u8]);
+
+impl<'a> Value for BytesValue<'a> {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_bytes(key, self.0, BytesKind::Stream)
+    }
+}
+
+/// Logs a number in a short form with an SI or IEC prefix, like
+/// `1.5k` or `2.3Mi`, for quickly scanning sizes and counts.
+///
+/// Numbers below 1000 (or 1024) are written as they are; larger ones
+/// are rounded to one decimal place.
+///
+/// ```rust
+/// # use slog_logfmt::values::HumanNumber;
+/// assert_eq!(HumanNumber::decimal(1500).to_string(), "1.5k");
+/// assert_eq!(HumanNumber::binary(1536).to_string(), "1.5Ki");
+/// assert_eq!(HumanNumber::binary(512).to_string(), "512");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct HumanNumber {
+    value: u64,
+    binary: bool,
+}
+
+impl HumanNumber {
+    /// Uses powers of 1000 with the prefixes `k`, `M`, `G`, ...
+    pub fn decimal(value: u64) -> HumanNumber {
+        HumanNumber {
+            value,
+            binary: false,
+        }
+    }
+
+    /// Uses powers of 1024 with the prefixes `Ki`, `Mi`, `Gi`, ...
+    pub fn binary(value: u64) -> HumanNumber {
+        HumanNumber {
+            value,
+            binary: true,
+        }
+    }
+}
+
+impl fmt::Display for HumanNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const DECIMAL: [&str; 6] = ["k", "M", "G", "T", "P", "E"];
+        const BINARY: [&str; 6] = ["Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+        let (base, prefixes) = if self.binary {
+            (1024.0, &BINARY)
+        } else {
+            (1000.0, &DECIMAL)
+        };
+        let round = |n: f64| (n * 10.0).round() / 10.0;
+        let mut scaled = self.value as f64;
+        let mut prefix = None;
+        for candidate in prefixes {
+            // Compare the rounded value, so that e.g. 999_999 becomes
+            // `1M` rather than `1000k`.
+            if round(scaled) < base {
+                break;
+            }
+            scaled /= base;
+            prefix = Some(candidate);
+        }
+        match prefix {
+            Some(prefix) => write!(f, "{}{}", round(scaled), prefix),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+impl Value for HumanNumber {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_arguments(key, &format_args!("{}", self))
+    }
+}
+
+/// Logs the entries of a map as one field each, named by the map's
+/// key and the entry's key, like `map.key1=v1 map.key2=v2`.
+///
+/// This works with anything that iterates over `(key, value)` pairs
+/// by reference, like a `HashMap` or `BTreeMap`. The entries are
+/// passed on via slog's `emit_serde`, so on this crate's drain they
+/// are flattened using its
+/// [`nest_separator`](crate::LogfmtBuilder::nest_separator) and other
+/// drains see them as a serde map. An empty map is logged as
+/// `map="{}"`, unless the drain's
+/// [`skip_empty_nested`](crate::LogfmtBuilder::skip_empty_nested) is
+/// on.
+///
+/// ```rust
+/// # use slog::{info, o, Drain, Logger};
+/// # use slog_logfmt::{values::MapValue, Logfmt};
+/// # use std::collections::BTreeMap;
+/// # let logger = Logger::root(slog::Discard, o!());
+/// let mut headers = BTreeMap::new();
+/// headers.insert("accept", "text/html");
+/// info!(logger, "request"; "headers" => MapValue(&headers));
+/// ```
+#[cfg(feature = "nested-values")]
+#[derive(Debug, Clone, Copy)]
+pub struct MapValue<'a, M: ?Sized>(pub &'a M);
+
+#[cfg(feature = "nested-values")]
+impl<'a, M, K, V> MapValue<'a, M>
+where
+    M: ?Sized,
+    &'a M: IntoIterator<Item = (K, V)>,
+    K: Display,
+    V: Value,
+{
+    /// Serializes the entries into an owned form.
+    fn capture(&self, record: &Record) -> CapturedMap {
+        let entries = self
+            .0
+            .into_iter()
+            .map(|(k, v)| {
+                let mut capture = Captured::Unit;
+                // Capturing can't fail, and values that fail to
+                // serialize themselves are logged as `()`.
+                let _ = v.serialize(record, "", &mut capture);
+                (k.to_string(), capture)
+            })
+            .collect();
+        CapturedMap(entries)
+    }
+}
+
+#[cfg(feature = "nested-values")]
+impl<'a, M, K, V> Value for MapValue<'a, M>
+where
+    M: ?Sized,
+    &'a M: IntoIterator<Item = (K, V)>,
+    K: Display,
+    V: Value,
+{
+    fn serialize(
+        &self,
+        record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, &self.capture(record))
+    }
+}
+
+/// The entries of a [`MapValue`], serialized.
+#[cfg(feature = "nested-values")]
+#[derive(Debug, Clone)]
+struct CapturedMap(Vec<(String, Captured)>);
+
+#[cfg(feature = "nested-values")]
+impl serde::Serialize for CapturedMap {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "nested-values")]
+impl slog::SerdeValue for CapturedMap {
+    fn as_serde(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+
+    fn to_sendable(&self) -> Box<dyn slog::SerdeValue + Send + 'static> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(feature = "nested-values")]
+impl Value for CapturedMap {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, self)
+    }
+}
+
+/// A single map entry's value, as it was emitted.
+#[cfg(feature = "nested-values")]
+#[derive(Debug, Clone)]
+enum Captured {
+    Unit,
+    Bool(bool),
+    Int(i128),
+    UInt(u128),
+    Float(f64),
+    Str(String),
+}
+
+#[cfg(feature = "nested-values")]
+impl serde::Serialize for Captured {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Captured::Unit => serializer.serialize_unit(),
+            Captured::Bool(b) => serializer.serialize_bool(*b),
+            Captured::Int(i) => serializer.serialize_i128(*i),
+            Captured::UInt(u) => serializer.serialize_u128(*u),
+            Captured::Float(f) => serializer.serialize_f64(*f),
+            Captured::Str(s) => serializer.serialize_str(s),
+        }
+    }
+}
+
+#[cfg(feature = "nested-values")]
+macro_rules! capture(
+    ($($f:ident($t:ty) => $variant:ident;)*) => {
+        $(
+            fn $f(&mut self, _key: Key, val: $t) -> slog::Result {
+                *self = Captured::$variant(val.into());
+                Ok(())
+            }
+        )*
+    };
+);
+
+#[cfg(feature = "nested-values")]
+impl Serializer for Captured {
+    capture! {
+        emit_bool(bool) => Bool;
+        emit_u8(u8) => UInt;
+        emit_u16(u16) => UInt;
+        emit_u32(u32) => UInt;
+        emit_u64(u64) => UInt;
+        emit_u128(u128) => UInt;
+        emit_i8(i8) => Int;
+        emit_i16(i16) => Int;
+        emit_i32(i32) => Int;
+        emit_i64(i64) => Int;
+        emit_i128(i128) => Int;
+        emit_f32(f32) => Float;
+        emit_f64(f64) => Float;
+        emit_str(&str) => Str;
+    }
+
+    fn emit_usize(&mut self, _key: Key, val: usize) -> slog::Result {
+        *self = Captured::UInt(val as u128);
+        Ok(())
+    }
+
+    fn emit_isize(&mut self, _key: Key, val: isize) -> slog::Result {
+        *self = Captured::Int(val as i128);
+        Ok(())
+    }
+
+    fn emit_unit(&mut self, _key: Key) -> slog::Result {
+        *self = Captured::Unit;
+        Ok(())
+    }
+
+    fn emit_arguments(&mut self, _key: Key, val: &Arguments) -> slog::Result {
+        *self = Captured::Str(fmt::format(*val));
+        Ok(())
+    }
+}
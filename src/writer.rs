@@ -0,0 +1,166 @@
+//! Writers to use as the output of a [`Logfmt`](crate::Logfmt) drain.
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Sends each complete line written to it over a channel.
+///
+/// Bytes are buffered until a newline is written; the line (without
+/// its trailing newline) is then sent as a `String`. This makes it
+/// easy to assert on output in tests, or to route formatted records
+/// to custom processing.
+///
+/// Writing fails with [`io::ErrorKind::BrokenPipe`] once the
+/// receiving end has been dropped.
+///
+/// # Example
+/// ```rust
+/// use slog::{info, o, Drain, Logger};
+/// use slog_logfmt::{writer::ChannelWriter, Logfmt};
+/// use std::sync::{mpsc, Mutex};
+///
+/// let (tx, rx) = mpsc::channel();
+/// let drain = Logfmt::new(ChannelWriter::new(tx)).no_prefix().build();
+/// let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+/// info!(logger, "hi"; "port" => 8080);
+/// assert_eq!(rx.recv().unwrap(), "port=8080");
+/// ```
+#[derive(Debug)]
+pub struct ChannelWriter {
+    sender: Sender<String>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    /// Creates a writer sending lines over `sender`.
+    pub fn new(sender: Sender<String>) -> ChannelWriter {
+        ChannelWriter {
+            sender,
+            buf: Vec::new(),
+        }
+    }
+
+    fn send_line(&mut self, end: usize) -> io::Result<()> {
+        let rest = self.buf.split_off(end + 1);
+        let mut line = std::mem::replace(&mut self.buf, rest);
+        line.pop();
+        let line = String::from_utf8(line)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+        self.sender
+            .send(line)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "receiver dropped"))
+    }
+}
+
+impl io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = self.buf.len();
+        self.buf.extend_from_slice(buf);
+        let mut search_from = start;
+        while let Some(pos) = self.buf[search_from..].iter().position(|&b| b == b'\n') {
+            self.send_line(search_from + pos)?;
+            search_from = 0;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Either of the standard output streams, so that one drain can write
+/// to both; see [`Logfmt::split_stderr`](crate::Logfmt::split_stderr).
+#[derive(Debug)]
+pub enum Stdio {
+    /// The process's standard output.
+    Stdout(io::Stdout),
+
+    /// The process's standard error.
+    Stderr(io::Stderr),
+}
+
+impl io::Write for Stdio {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stdio::Stdout(out) => out.write(buf),
+            Stdio::Stderr(err) => err.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stdio::Stdout(out) => out.flush(),
+            Stdio::Stderr(err) => err.flush(),
+        }
+    }
+}
+
+/// Flushes the wrapped writer periodically from a background thread,
+/// instead of after every record.
+///
+/// This is what [`LogfmtBuilder::flush_interval`](crate::LogfmtBuilder::flush_interval)
+/// wraps the drain's writer in. Flushing this writer itself does
+/// nothing; the background thread flushes the inner writer every
+/// interval, and once more when this writer is dropped.
+pub struct IntervalFlush<W: io::Write + Send + 'static> {
+    inner: Arc<Mutex<W>>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<W: io::Write + Send + 'static> IntervalFlush<W> {
+    /// Wraps `inner`, flushing it every `interval`.
+    pub fn new(inner: W, interval: Duration) -> IntervalFlush<W> {
+        let inner = Arc::new(Mutex::new(inner));
+        let (stop, stopped) = mpsc::channel::<()>();
+        let flushed = Arc::clone(&inner);
+        let thread = thread::Builder::new()
+            .name("logfmt-flush".to_string())
+            .spawn(move || loop {
+                let done = !matches!(
+                    stopped.recv_timeout(interval),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                if let Ok(mut inner) = flushed.lock() {
+                    // There's nobody to report errors to; the next
+                    // write will most likely run into them, too.
+                    let _ = inner.flush();
+                }
+                if done {
+                    return;
+                }
+            })
+            .expect("failed to spawn the flush thread");
+        IntervalFlush {
+            inner,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<W: io::Write + Send + 'static> io::Write for IntervalFlush<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner
+            .lock()
+            .map_err(|_| io::Error::other("writer poisoned"))?
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W: io::Write + Send + 'static> Drop for IntervalFlush<W> {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
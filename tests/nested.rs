@@ -0,0 +1,160 @@
+#![cfg(feature = "nested-values")]
+
+use slog::{info, o, Drain, Key, Logger, Record, Serializer};
+use slog_logfmt::values::MapValue;
+use slog_logfmt::{Logfmt, Redaction};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+struct Nested(BTreeMap<&'static str, BTreeMap<&'static str, BTreeMap<&'static str, u32>>>);
+
+impl slog::Value for Nested {
+    fn serialize(
+        &self,
+        _record: &Record,
+        key: Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_serde(key, self)
+    }
+}
+
+impl serde::Serialize for Nested {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl slog::SerdeValue for Nested {
+    fn as_serde(&self) -> &dyn erased_serde::Serialize {
+        &self.0
+    }
+
+    fn to_sendable(&self) -> Box<dyn slog::SerdeValue + Send + 'static> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Default, Clone)]
+struct Capture(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl io::Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Capture {
+    fn snapshot(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().get_ref().clone()).unwrap()
+    }
+}
+
+fn nested() -> Nested {
+    let mut c = BTreeMap::new();
+    c.insert("d", 1);
+    let mut b = BTreeMap::new();
+    b.insert("c", c);
+    let mut a = BTreeMap::new();
+    a.insert("b", b);
+    Nested(a)
+}
+
+#[test]
+fn flattens_into_dotted_keys() {
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone()).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(logger, "hi"; "f" => nested());
+    assert_eq!(capture.snapshot(), "f.b.c.d=1\n");
+}
+
+#[test]
+fn max_nest_depth_collapses_deeper_levels() {
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone())
+        .no_prefix()
+        .max_nest_depth(2)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(logger, "hi"; "f" => nested());
+    assert_eq!(capture.snapshot(), "f.b.c=\"{\\\"d\\\":1}\"\n");
+}
+
+#[test]
+fn custom_separator() {
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone())
+        .no_prefix()
+        .nest_separator("_")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(logger, "hi"; "f" => nested());
+    assert_eq!(capture.snapshot(), "f_b_c_d=1\n");
+}
+
+#[test]
+fn map_value_entries() {
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone()).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let mut map = BTreeMap::new();
+    map.insert("key1", "v1");
+    map.insert("key2", "v2");
+    info!(logger, "hi"; "map" => MapValue(&map));
+    assert_eq!(capture.snapshot(), "map.key1=v1 map.key2=v2\n");
+}
+
+#[test]
+fn empty_map_value() {
+    let empty: BTreeMap<&str, u32> = BTreeMap::new();
+
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone()).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(logger, "hi"; "map" => MapValue(&empty), "n" => 1);
+    assert_eq!(capture.snapshot(), "n=1 map=\"{}\"\n");
+
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone())
+        .no_prefix()
+        .skip_empty_nested(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    info!(logger, "hi"; "map" => MapValue(&empty), "n" => 1);
+    assert_eq!(capture.snapshot(), "n=1\n");
+}
+
+#[test]
+fn redacts_nested_keys() {
+    fn redact(key: &&str) -> Redaction {
+        match *key {
+            "password" => Redaction::Mark("<redacted>"),
+            "c" => Redaction::Skip,
+            _ => Redaction::Plain,
+        }
+    }
+
+    let capture = Capture::default();
+    let drain = Logfmt::new(capture.clone())
+        .no_prefix()
+        .redact(redact)
+        .redact_values(|value| (value == "bob").then_some(Cow::Borrowed("<user>")))
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let mut login = BTreeMap::new();
+    login.insert(String::from("password"), "hunter2");
+    login.insert(String::from("user"), "bob");
+    info!(logger, "hi"; "login" => MapValue(&login), "f" => nested());
+    assert_eq!(
+        capture.snapshot(),
+        "login.password=\"<redacted>\" login.user=\"<user>\"\n"
+    );
+}
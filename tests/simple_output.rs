@@ -1,11 +1,21 @@
 use core::fmt;
 use slog::{debug, o, Drain, Error, Logger, Serializer, KV};
-use slog_logfmt::{Logfmt, Redaction};
+use slog_logfmt::batch::Batched;
+use slog_logfmt::ring::RingBuffer;
+use slog_logfmt::split::LevelSplit;
+use slog_logfmt::values::{BytesValue, HumanNumber};
+use slog_logfmt::writer::ChannelWriter;
+use slog_logfmt::{
+    BoolStyle, EmptyKeyPolicy, ErrorContext, ErrorStyle, EscapeStyle, InvalidUtf8Policy,
+    LevelNumbering, LevelStyle, Logfmt, PanicDetails, Redaction, SampleDecision, TagMarker,
+    TimestampFormat, TypeClass,
+};
 use std::fmt::Arguments;
 use std::io;
-use std::io::Cursor;
+use std::io::{Cursor, Write};
 use std::str::from_utf8;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use test_case::test_case;
 
 #[derive(Clone, Default)]
@@ -150,7 +160,7 @@ fn prefixed_stuff() {
             write!(&mut io, "[")?;
             {
                 let mut serializer = PrefixSerializer { io: &mut io };
-                rec.kv().serialize(&rec, &mut serializer)?;
+                rec.kv().serialize(rec, &mut serializer)?;
             }
             write!(&mut io, "] ")?;
             Ok(())
@@ -189,3 +199,2346 @@ fn redactions() {
         "DEBG | #tag\thi there\tlogger=tests secret=\"***\"\n"
     );
 }
+
+fn environment_drain(output: LogCapture, deterministic: bool) -> Logfmt<LogCapture> {
+    let builder = Logfmt::new(output)
+        .no_prefix()
+        .timestamp(TimestampFormat::Rfc3339)
+        .with_pid(true)
+        .with_hostname(true)
+        .with_instance_id(true)
+        .with_seq(true)
+        .with_elapsed(true)
+        .with_delta(true);
+    if deterministic {
+        builder.deterministic().build()
+    } else {
+        builder.build()
+    }
+}
+
+#[test]
+fn deterministic_output() {
+    fn run(deterministic: bool) -> String {
+        let output = LogCapture::default();
+        let drain = environment_drain(output.clone(), deterministic);
+        let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+        debug!(logger, "hi"; "n" => 1);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        debug!(logger, "again");
+        drop(logger);
+        output.snapshot_str()
+    }
+
+    let first = run(true);
+    assert_eq!(first, run(true));
+    assert_eq!(
+        first,
+        "ts=\"1970-01-01T00:00:00Z\" instance=00000000000000000000000000 pid=0 \
+         host=localhost n=1 delta_ms=0 seq=1 elapsed_ms=0\n\
+         ts=\"1970-01-01T00:00:00Z\" instance=00000000000000000000000000 pid=0 \
+         host=localhost delta_ms=0 seq=2 elapsed_ms=0\n"
+    );
+    assert_ne!(run(false), first);
+}
+
+#[test]
+fn injected_process_identity() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .pid(42)
+        .hostname("web-1")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+
+    assert_eq!(output.snapshot_str(), "pid=42 host=web-1 n=1\n");
+}
+
+#[test_case(true, false, "DEBG | #tag\thi there\tlogger=tests";
+            "message in prefix only")]
+#[test_case(false, false, "DEBG | #tag\tlogger=tests";
+            "no message at all")]
+#[test_case(true, true, "DEBG | #tag\thi there\tmsg=\"hi there\" logger=tests";
+            "message in prefix and field")]
+#[test_case(false, true, "DEBG | #tag\tmsg=\"hi there\" logger=tests";
+            "message in field only")]
+fn prefix_and_field_messages(prefix_msg: bool, print_msg: bool, expected: &str) {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .prefix_msg(prefix_msg)
+        .print_msg(print_msg)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("logger" => "tests"));
+    debug!(logger, #"tag", "hi there");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str().trim_end(), expected);
+}
+
+#[test]
+fn value_budget_truncates_largest_first() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .value_budget(50)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "small" => "a".repeat(10), "medium" => "b".repeat(20), "large" => "c".repeat(50));
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        format!(
+            "large={} medium={} small={} _truncated=1\n",
+            "c".repeat(20),
+            "b".repeat(20),
+            "a".repeat(10)
+        )
+    );
+}
+
+#[test]
+fn value_budget_skips_built_in_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .value_budget(3)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    slog::info!(logger, "hi"; "a" => "hello");
+
+    assert_eq!(output.snapshot_str(), "level=INFO a=hel _truncated=1\n");
+}
+
+struct FailAfter(usize);
+
+impl io::Write for FailAfter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        if self.0 == 0 {
+            return Err(io::Error::other("disk full"));
+        }
+        let written = buf.len().min(self.0);
+        self.0 -= written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_errors_carry_context() {
+    use slog::{b, record, Level};
+    use std::error::Error as _;
+
+    let drain = Logfmt::new(FailAfter(3)).no_prefix().build();
+    let err = drain
+        .log(
+            &record!(Level::Info, "", &format_args!("hi"), b!("b" => 2, "a" => 1)),
+            &o!().into(),
+        )
+        .unwrap_err();
+
+    assert_eq!(err.context(), &ErrorContext::Field("b".to_string()));
+    assert_eq!(err.to_string(), "logfmt: failed writing field \"b\"");
+    assert_eq!(err.source().unwrap().to_string(), "disk full");
+}
+
+#[test]
+fn level_gated_keys() {
+    use slog::{info, Level};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .level_gated_keys(|key, level| *key != "debug_detail" || !level.is_at_least(Level::Info))
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "debug_detail" => "lots", "id" => 1);
+    info!(logger, "hi"; "debug_detail" => "lots", "id" => 2);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "id=1 debug_detail=lots\nid=2\n");
+}
+
+#[test]
+fn report_field_count() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .redact(|&key| match key {
+            "password" => Redaction::Skip,
+            _ => Redaction::Plain,
+        })
+        .report_field_count(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "user" => "asf", "password" => "hunter2", "id" => 1);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "id=1 user=asf _fields=2\n");
+}
+
+#[test_case(InvalidUtf8Policy::Replace, "f=\"a\u{FFFD}b\"")]
+#[test_case(InvalidUtf8Policy::Escape, r#"f="a\\xffb""#)]
+#[test_case(InvalidUtf8Policy::Skip, "f=ab")]
+fn invalid_utf8_bytes(policy: InvalidUtf8Policy, expected: &str) {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .invalid_utf8(policy)
+        .build();
+    let drain = Mutex::new(drain).fuse();
+    let logger = Logger::root(drain, o!());
+
+    debug!(logger, ""; "f" => BytesValue(b"a\xffb"));
+    drop(logger);
+    assert_eq!(output.snapshot_str().trim_end(), expected);
+}
+
+/// A custom drain that serializes logger values and record values
+/// in two separate passes, re-using the logfmt field formatting.
+struct TwoPassDrain {
+    logfmt: Logfmt<io::Sink>,
+    output: LogCapture,
+}
+
+impl Drain for TwoPassDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &slog::Record, values: &slog::OwnedKVList) -> Result<(), io::Error> {
+        let mut buf = Vec::new();
+
+        let mut first_pass = self.logfmt.serializer(&mut buf);
+        values.serialize(record, &mut first_pass)?;
+        let was_first = first_pass.is_first();
+
+        let mut second_pass = self.logfmt.serializer(&mut buf);
+        if !was_first {
+            second_pass.mark_not_first();
+        }
+        record.kv().serialize(record, &mut second_pass)?;
+
+        buf.push(b'\n');
+        self.output.clone().write_all(&buf)
+    }
+}
+
+#[test]
+fn serializer_separator_state_across_passes() {
+    let output = LogCapture::default();
+    let drain = TwoPassDrain {
+        logfmt: Logfmt::new(io::sink()).build(),
+        output: output.clone(),
+    };
+    let root = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(root, "hi"; "only" => "record");
+    let logger = root.new(o!("logger" => "tests"));
+    debug!(logger, "hi"; "a" => 1, "b" => "two");
+    debug!(logger, "hi");
+
+    drop((root, logger));
+    assert_eq!(
+        output.snapshot_str(),
+        "only=record\nlogger=tests b=two a=1\nlogger=tests\n"
+    );
+}
+
+#[test]
+fn delta_timing() {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .with_delta(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "one");
+    sleep(Duration::from_millis(20));
+    debug!(logger, "two");
+    sleep(Duration::from_millis(60));
+    debug!(logger, "three");
+
+    drop(logger);
+    let deltas: Vec<u64> = output
+        .snapshot_str()
+        .lines()
+        .map(|line| line.strip_prefix("delta_ms=").unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(deltas[0], 0);
+    assert!(deltas[1] >= 20, "{:?}", deltas);
+    assert!(deltas[2] >= 60 && deltas[2] > deltas[1], "{:?}", deltas);
+}
+
+#[test]
+fn leading_level_before_custom_prefix() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .set_prefix(|io, rec| {
+            write!(io, "[{}] ", rec.msg())?;
+            Ok(())
+        })
+        .leading_level(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("logger" => "tests"));
+    debug!(logger, "hi there");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "DEBG [hi there] logger=tests\n");
+}
+
+#[test_case(TimestampFormat::Rfc3339, r#"ts="2020-03-12T08:00:00Z""#)]
+#[test_case(TimestampFormat::Rfc3339Millis, r#"ts="2020-03-12T08:00:00.123Z""#)]
+#[test_case(TimestampFormat::UnixSeconds, "ts=1584000000")]
+#[test_case(TimestampFormat::UnixMillis, "ts=1584000000123")]
+#[test_case(TimestampFormat::UnixNanos, "ts=1584000000123456789")]
+#[test_case(TimestampFormat::Base36Millis, "ts=0k7ogpabf")]
+fn timestamp_formats(format: TimestampFormat, expected: &str) {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .timestamp(format)
+        .clock(|| UNIX_EPOCH + Duration::new(1_584_000_000, 123_456_789))
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+
+    debug!(logger, "hi");
+    drop(logger);
+    assert_eq!(output.snapshot_str().trim_end(), expected);
+}
+
+#[test]
+fn base36_timestamps_decode() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .timestamp(TimestampFormat::Base36Millis)
+        .clock(|| UNIX_EPOCH + Duration::from_millis(1_700_000_000_999))
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+
+    debug!(logger, "hi");
+    drop(logger);
+    let output = output.snapshot_str();
+    let ts = output.trim_end().strip_prefix("ts=").unwrap();
+    assert_eq!(ts.len(), 9);
+    assert_eq!(u64::from_str_radix(ts, 36), Ok(1_700_000_000_999));
+}
+
+#[test]
+fn type_suffix_annotations() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .type_suffix(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "count" => 5_i64, "verbose" => true, "name" => "asf");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "name:str=asf verbose:bool=true count:i64=5\n"
+    );
+}
+
+struct PanickyDebug;
+
+impl fmt::Debug for PanickyDebug {
+    fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        panic!("oh no")
+    }
+}
+
+#[test]
+fn catch_value_panics() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .catch_value_panics(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "bad" => ?PanickyDebug, "good" => 1);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "good=1 bad=\"<panicked>\"\n");
+}
+
+#[test]
+fn marked_redactions() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .redact(|&key| match key {
+            "password" => Redaction::Mark("<redacted>"),
+            "token" => Redaction::Skip,
+            _ => Redaction::Plain,
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "user" => "asf", "password" => "hunter2", "token" => "abc");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "password=\"<redacted>\" user=asf\n");
+}
+
+#[test]
+fn binary_safe_output() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .set_prefix(|io, _rec| {
+            io.write_all(b"\xff\xfe ")?;
+            Ok(())
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "f" => "\u{e9}");
+
+    drop(logger);
+    assert_eq!(output.snapshot_buf(), b"\xff\xfe f=\"\xc3\xa9\"\n");
+}
+
+#[test]
+fn indentation() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).no_prefix().indent(2).build();
+    let drain = Arc::new(Mutex::new(drain));
+    let logger = Logger::root(drain.clone().fuse(), o!());
+    debug!(logger, "hi"; "at" => "two");
+    drain.lock().unwrap().set_indent(4);
+    debug!(logger, "hi"; "at" => "four");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "  at=two\n    at=four\n");
+}
+
+#[test]
+fn auto_correlation_id() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .auto_correlation_id(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "forgot the id");
+    debug!(logger, "has an id"; "trace_id" => "abc123");
+
+    drop(logger);
+    let out = output.snapshot_str();
+    let lines: Vec<&str> = out.lines().collect();
+    let generated = lines[0].strip_prefix("trace_id=").unwrap();
+    assert_eq!(generated.len(), 26);
+    assert!(generated.chars().all(|c| c.is_ascii_alphanumeric()));
+    assert_eq!(lines[1], "trace_id=abc123");
+}
+
+#[test]
+fn separate_key_and_value_escaping() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .key_escape_style(EscapeStyle::Sanitize)
+        .value_escape_style(EscapeStyle::Quote)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "my key" => "line1\nline2");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "my_key=\"line1\\nline2\"\n");
+}
+
+#[test]
+fn channel_writer_sends_lines() {
+    let (tx, rx) = mpsc::channel();
+    let drain = Logfmt::new(ChannelWriter::new(tx))
+        .no_prefix()
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "one"; "n" => 1);
+    debug!(logger, "two"; "n" => 2);
+
+    drop(logger);
+    let lines: Vec<String> = rx.iter().collect();
+    assert_eq!(lines, vec!["n=1", "n=2"]);
+}
+
+#[test]
+fn column_layout() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .columns(&[("user", 11), ("status", 14)])
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "req"; "n" => 1, "status" => "ok", "user" => "bob");
+    debug!(logger, "req"; "n" => 2, "status" => "failed", "user" => "alice");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | req\tuser=bob    status=ok      n=1\n\
+         DEBG | req\tuser=alice  status=failed  n=2\n"
+    );
+}
+
+#[test]
+fn prefix_only_tagged() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .prefix_only_tagged(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, #"audit", "login"; "user" => "asf");
+    debug!(logger, "logout"; "user" => "asf");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | #audit\tlogin\tuser=asf\nuser=asf\n"
+    );
+}
+
+#[test]
+fn prefix_only_tagged_with_default_tag() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .default_tag("gen")
+        .prefix_only_tagged(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "user" => "asf");
+
+    assert_eq!(output.snapshot_str(), "DEBG | #gen\thi\tuser=asf\n");
+}
+
+#[test_case(LevelNumbering::Syslog => "level=DEBG level_num=7\nlevel=WARN level_num=4\n"; "syslog")]
+#[test_case(LevelNumbering::Slog => "level=DEBG level_num=5\nlevel=WARN level_num=3\n"; "slog")]
+fn level_num(numbering: LevelNumbering) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .level_num(numbering)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi");
+    slog::warn!(logger, "careful");
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test_case("007" => "k=\"007\"\n"; "leading zero")]
+#[test_case("3.14" => "k=\"3.14\"\n"; "decimal")]
+#[test_case("abc" => "k=abc\n"; "text")]
+fn quote_numeric_strings(value: &str) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .quote_numeric_strings(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "k" => value);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn merge_builder_options() {
+    let base = Logfmt::new(io::sink()).no_prefix().force_quotes();
+    let overrides = Logfmt::new(io::sink()).print_level(true);
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .merge(&base)
+        .merge(&overrides)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "user" => "asf");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "level=\"DEBG\" user=\"asf\"\n");
+}
+
+#[derive(Clone, Default)]
+struct FlushCounter(Arc<Mutex<usize>>);
+
+impl Write for FlushCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        *self.0.lock().unwrap() += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn flush_interval() {
+    let flushes = FlushCounter::default();
+    let drain = Logfmt::new(flushes.clone())
+        .flush_interval(std::time::Duration::from_millis(10))
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi");
+
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    assert!(*flushes.0.lock().unwrap() >= 1);
+
+    drop(logger);
+    let after_drop = *flushes.0.lock().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    assert_eq!(*flushes.0.lock().unwrap(), after_drop);
+}
+
+#[test]
+fn logger_path() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .track_logger_path(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("component" => "app"));
+    let http = logger.new(o!("component" => "http"));
+    debug!(http, "hi");
+
+    drop(logger);
+    drop(http);
+    assert_eq!(
+        output.snapshot_str(),
+        "component=http component=app logger_path=app.http\n"
+    );
+}
+
+#[test]
+fn locale_independent_numbers() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .float_precision(2)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "max" => u128::MAX, "min" => i128::MIN, "big" => 1234567.891f64);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "big=1234567.89 min=-170141183460469231731687303715884105728 \
+         max=340282366920938463463374607431768211455\n"
+    );
+}
+
+#[test]
+fn pause_and_resume() {
+    let output = LogCapture::default();
+    let drain = Arc::new(Mutex::new(Logfmt::new(output.clone()).no_prefix().build()));
+    let logger = Logger::root(drain.clone().fuse(), o!());
+    drain.lock().unwrap().set_paused(true);
+    debug!(logger, "hi"; "n" => 1);
+    drain.lock().unwrap().set_paused(false);
+    debug!(logger, "hi"; "n" => 2);
+
+    assert_eq!(output.snapshot_str(), "n=2\n");
+}
+
+#[test]
+fn error_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .error_fields(slog::Level::Error, |_, serializer| {
+            serializer.emit_bool("alert", true)
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    slog::info!(logger, "fine"; "n" => 1);
+    slog::error!(logger, "broken"; "n" => 2);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "n=1\nn=2 alert=true\n");
+}
+
+#[test]
+fn configured_key_names() {
+    let drain = Logfmt::new(io::sink()).build();
+    assert_eq!(drain.level_key(), "level");
+    assert_eq!(drain.msg_key(), "msg");
+    assert_eq!(drain.ts_key(), "ts");
+    assert_eq!(drain.correlation_id_key(), "trace_id");
+    assert_eq!(drain.logger_path_key(), None);
+
+    let drain = Logfmt::new(io::sink())
+        .correlation_id_key("request_id")
+        .logger_path_key("module")
+        .build();
+    assert_eq!(drain.correlation_id_key(), "request_id");
+    assert_eq!(drain.logger_path_key(), Some("module"));
+}
+
+#[derive(Debug)]
+struct Failure(&'static str, Option<Box<Failure>>);
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl std::error::Error for Failure {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.1.as_deref().map(|e| e as _)
+    }
+}
+
+#[test]
+fn errors_into_msg() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .print_msg(true)
+        .errors_into_msg(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let error = Failure(
+        "timeout",
+        Some(Box::new(Failure("connection refused", None))),
+    );
+    slog::error!(logger, "request failed"; "status" => 500, "err" => #&error);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "ERRO | request failed: timeout: connection refused\t\
+         msg=\"request failed: timeout: connection refused\" status=500\n"
+    );
+}
+
+#[test]
+fn errors_into_renamed_msg() {
+    let output = LogCapture::default();
+    let renames = std::iter::once(("msg".to_string(), "message".to_string())).collect();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_msg(true)
+        .rename_keys(renames)
+        .errors_into_msg(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let error = Failure("timeout", None);
+    slog::error!(logger, "request failed"; "err" => #&error);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "message=\"request failed: timeout\"\n"
+    );
+}
+
+#[test]
+fn errors_into_msg_before_field_count() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_msg(true)
+        .errors_into_msg(true)
+        .report_field_count(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let error = Failure("boom", None);
+    slog::error!(logger, "failed"; "err" => #&error, "a" => 1);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "msg=\"failed: boom\" a=1 _fields=2\n"
+    );
+}
+
+#[test_case(", ", "a,b" => "n=1, list=\"a,b\"\n"; "comma space")]
+#[test_case(" | ", "a|b" => "n=1 | list=\"a|b\"\n"; "pipe")]
+#[test_case("/", "a/b" => "n=1/list=\"a/b\"\n"; "unquoted character")]
+fn multi_char_separator(separator: &str, list: &str) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .field_separator(separator)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "list" => list, "n" => 1);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn sample_rate_field() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .sample_rate(2)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    for n in 0..4 {
+        debug!(logger, "hi"; "n" => n);
+    }
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "n=0 _sample_rate=2\nn=2 _sample_rate=2\n"
+    );
+}
+
+#[test_case(EscapeStyle::Quote, false, "a\rb" => "k=\"a\\rb\"\n"; "quoted cr")]
+#[test_case(EscapeStyle::Raw, false, "a\rb" => "k=a\\rb\n"; "raw cr")]
+#[test_case(EscapeStyle::Quote, false, "a\nb" => "k=\"a\\nb\"\n"; "quoted lf")]
+#[test_case(EscapeStyle::Quote, false, "a\r\nb" => "k=\"a\\r\\nb\"\n"; "quoted crlf")]
+#[test_case(EscapeStyle::Quote, true, "a\r\nb" => "k=\"a\\nb\"\n"; "collapsed crlf")]
+fn carriage_returns(style: EscapeStyle, collapse: bool, value: &str) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .value_escape_style(style)
+        .collapse_crlf(collapse)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "k" => value);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn slog_json_compat() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .slog_json_compat()
+        .clock(|| UNIX_EPOCH + Duration::new(1_584_000_000, 0))
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    slog::info!(logger, "started"; "port" => 8080);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "ts=\"2020-03-12T08:00:00Z\" level=INFO msg=started port=8080\n"
+    );
+}
+
+/// Looks for a `force_log=true` field.
+#[derive(Default)]
+struct ForceLog(bool);
+
+impl Serializer for ForceLog {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        if key == "force_log" && val.to_string() == "true" {
+            self.0 = true;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn sample_override() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .sample_rate(100)
+        .sample_override(|record| {
+            let mut force = ForceLog::default();
+            record.kv().serialize(record, &mut force).unwrap();
+            if force.0 {
+                SampleDecision::Force
+            } else {
+                SampleDecision::Default
+            }
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    for n in 0..3 {
+        debug!(logger, "hi"; "n" => n);
+        debug!(logger, "hi"; "n" => n, "force_log" => true);
+    }
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "n=0 _sample_rate=100\n\
+         force_log=true n=0 _sample_rate=1\n\
+         force_log=true n=1 _sample_rate=1\n\
+         force_log=true n=2 _sample_rate=1\n"
+    );
+}
+
+#[test]
+fn collapse_msg_whitespace() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .collapse_msg_whitespace(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "user   said:\t \thi"; "n" => 1);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "DEBG | user said: hi\tn=1\n");
+}
+
+#[test]
+fn level_split() {
+    use slog::Level;
+
+    let errors = LogCapture::default();
+    let infos = LogCapture::default();
+    let all = LogCapture::default();
+    let drain = LevelSplit::new()
+        .route(
+            Level::Error,
+            Level::Critical,
+            Logfmt::new(errors.clone()).no_prefix().build(),
+        )
+        .route(
+            Level::Info,
+            Level::Info,
+            Logfmt::new(infos.clone()).no_prefix().build(),
+        )
+        .catch_all(Logfmt::new(all.clone()).no_prefix().build());
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    slog::info!(logger, "hi"; "n" => 1);
+    slog::error!(logger, "oops"; "n" => 2);
+    slog::crit!(logger, "fire"; "n" => 3);
+
+    assert_eq!(errors.snapshot_str(), "n=2\nn=3\n");
+    assert_eq!(infos.snapshot_str(), "n=1\n");
+    assert_eq!(all.snapshot_str(), "n=1\nn=2\nn=3\n");
+}
+
+#[test]
+fn build_info() {
+    std::env::set_var("SLOG_LOGFMT_TEST_GIT_SHA", "def456");
+    let output = LogCapture::default();
+    let literal = Logfmt::new(output.clone())
+        .no_prefix()
+        .build_info("abc123")
+        .build();
+    let from_env = Logfmt::new(output.clone())
+        .no_prefix()
+        .build_info_from_env("SLOG_LOGFMT_TEST_GIT_SHA")
+        .build();
+    let logger = Logger::root(Mutex::new(literal).fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+    let logger = Logger::root(Mutex::new(from_env).fuse(), o!());
+    debug!(logger, "hi"; "n" => 2);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "build=abc123 n=1\nbuild=def456 n=2\n"
+    );
+}
+
+#[test]
+fn char_replacement_table() {
+    let mut table = std::collections::HashMap::new();
+    table.insert('=', "\\x3d");
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .value_escape_style(EscapeStyle::Raw)
+        .replace_chars(table)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "query" => "a=1");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "query=a\\x3d1\n");
+}
+
+#[test]
+fn periodic_summaries() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .summary_every(10)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    for n in 0..25 {
+        match n {
+            0 | 1 => slog::error!(logger, "oops"),
+            10..=12 => slog::warn!(logger, "careful"),
+            _ => slog::info!(logger, "fine"),
+        }
+    }
+
+    let out = output.snapshot_str();
+    let summaries: Vec<&str> = out.lines().filter(|l| l.starts_with("_summary")).collect();
+    assert_eq!(
+        summaries,
+        vec![
+            "_summary=1 total=10 errors=2 warnings=0",
+            "_summary=1 total=10 errors=0 warnings=3",
+        ]
+    );
+    assert_eq!(out.lines().count(), 27);
+}
+
+#[test]
+fn flag_style_bools() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .bool_style(BoolStyle::FlagPresence)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "n" => 1, "dry_run" => false, "verbose" => true, "m" => 2);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "m=2 verbose n=1\n");
+}
+
+#[test]
+fn split_writer_by_level() {
+    let low = LogCapture::default();
+    let high = LogCapture::default();
+    let drain = Logfmt::new(low.clone())
+        .no_prefix()
+        .split_writer(slog::Level::Warning, high.clone())
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+    slog::info!(logger, "hi"; "n" => 2);
+    slog::warn!(logger, "careful"; "n" => 3);
+    slog::error!(logger, "oops"; "n" => 4);
+
+    assert_eq!(low.snapshot_str(), "n=1\nn=2\n");
+    assert_eq!(high.snapshot_str(), "n=3\nn=4\n");
+}
+
+#[test_case(ErrorStyle::Display => "err=timeout\n"; "display")]
+#[test_case(ErrorStyle::DisplayChain => "err=\"timeout: connection refused\"\n"; "display chain")]
+#[test_case(ErrorStyle::Debug => "err=\"Failure(\\\"timeout\\\", Some(Failure(\\\"connection refused\\\", None)))\"\n"; "debug")]
+fn error_styles(style: ErrorStyle) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .error_style(style)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    let error = Failure(
+        "timeout",
+        Some(Box::new(Failure("connection refused", None))),
+    );
+    slog::error!(logger, "request failed"; "err" => #&error);
+
+    output.snapshot_str()
+}
+
+#[test]
+fn replace_writer() {
+    let first = LogCapture::default();
+    let second = LogCapture::default();
+    let drain = Arc::new(Mutex::new(Logfmt::new(first.clone()).no_prefix().build()));
+    let logger = Logger::root(drain.clone().fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+    drain.lock().unwrap().replace_writer(second.clone());
+    debug!(logger, "hi"; "n" => 2);
+
+    assert_eq!(first.snapshot_str(), "n=1\n");
+    assert_eq!(second.snapshot_str(), "n=2\n");
+}
+
+#[test_case(17, "short" => "short=1\n"; "short key")]
+#[test_case(17, "very_long_key_name" => "very_long_ke_f5e6=1\n"; "long key")]
+#[test_case(3, "abcde" => "abcde=1\n"; "small limit raised")]
+#[test_case(3, "abcdefghij" => "_1ef2=1\n"; "small limit")]
+fn max_key_len(len: usize, key: &'static str) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .max_key_len(len)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!(key => 1));
+    debug!(logger, "hi");
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn max_key_len_covers_added_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .max_key_len(6)
+        .default_fields(&[("tenant_identifier", "x")])
+        .derived_field("derived_key", |_| Some("y".to_string()))
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "a" => 1);
+
+    assert_eq!(output.snapshot_str(), "a=1 t_86dd=x d_7572=y\n");
+}
+
+#[test]
+fn prefix_dedup() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .prefix_msg(false)
+        .prefix_dedup(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    for n in 1..=3 {
+        debug!(logger, "hi"; "n" => n);
+    }
+    slog::warn!(logger, "careful"; "n" => 4);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "DEBG | n=1\nn=2\nn=3\nWARN | n=4\n");
+}
+
+#[test]
+fn list_redacted_keys() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .redact(|&key| match key {
+            "password" => Redaction::Mark("<redacted>"),
+            "token" => Redaction::Skip,
+            _ => Redaction::Plain,
+        })
+        .list_redacted_keys(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "user" => "asf", "password" => "hunter2", "token" => "abc");
+    debug!(logger, "hi"; "user" => "asf");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "password=\"<redacted>\" user=asf _redacted_keys=\"token,password\"\nuser=asf\n"
+    );
+}
+
+#[test]
+fn key_priority() {
+    let mut priority = std::collections::HashMap::new();
+    priority.insert("level".to_string(), 1);
+    priority.insert("msg".to_string(), 2);
+    priority.insert("id".to_string(), 0);
+    priority.insert("debug".to_string(), 200);
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .print_msg(true)
+        .key_priority(priority)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "b" => 2, "debug" => true, "a" => 1, "id" => 7);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "id=7 level=DEBG msg=hi a=1 b=2 debug=true\n"
+    );
+}
+
+#[test]
+fn tsv_output() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .print_msg(true)
+        .tsv(&["msg", "user", "status"])
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "login"; "user" => "asf", "status" => 200, "extra" => 1);
+    debug!(logger, "tab\there"; "status" => 500);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "login\tasf\t200\ntab\\there\t\t500\n"
+    );
+}
+
+#[test]
+fn tsv_without_summary() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .tsv(&["n"])
+        .summary_every(1)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+
+    assert_eq!(output.snapshot_str(), "1\n");
+}
+
+#[test]
+fn escape_prefix_separator() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .escape_prefix_separator(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, #"a | b", "left | right"; "n" => 1);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | #a \\| b\tleft \\| right\tn=1\n"
+    );
+}
+
+struct Point(i32, i32);
+
+impl slog::Value for Point {
+    fn serialize(
+        &self,
+        _record: &slog::Record,
+        _key: slog::Key,
+        serializer: &mut dyn Serializer,
+    ) -> slog::Result {
+        serializer.emit_i32("x", self.0)?;
+        serializer.emit_i32("y", self.1)
+    }
+}
+
+#[test]
+fn value_emitting_several_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "moved"; "n" => 1, "pos" => Point(3, -4), "m" => 2);
+
+    assert_eq!(output.snapshot_str(), "m=2 x=3 y=-4 n=1\n");
+}
+
+#[test_case(3.0 => "x=3\n"; "integral")]
+#[test_case(3.5 => "x=3.5\n"; "fractional")]
+#[test_case(-2.0 => "x=-2\n"; "negative")]
+#[test_case(1e20 => "x=100000000000000000000\n"; "beyond i64")]
+#[test_case(f64::NAN => "x=NaN\n"; "nan")]
+#[test_case(f64::INFINITY => "x=inf\n"; "infinity")]
+fn float_integer_shortcut(x: f64) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .float_integer_shortcut(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "x" => x);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn default_tag() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .default_tag("general")
+        .print_tag(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, #"audit", "login");
+    debug!(logger, "hi");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | #audit\tlogin\ttag=audit\nDEBG | #general\thi\ttag=general\n"
+    );
+}
+
+struct FakePanic;
+
+impl PanicDetails for FakePanic {
+    fn message(&self) -> Option<&str> {
+        Some("index out of bounds")
+    }
+
+    fn location(&self) -> Option<(&str, u32, u32)> {
+        Some(("src/main.rs", 12, 5))
+    }
+}
+
+#[test]
+fn log_panic() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).build();
+    drain.log_panic(&FakePanic).unwrap();
+
+    assert_eq!(
+        output.snapshot_str(),
+        "ERRO | index out of bounds\tlocation=\"src/main.rs:12:5\"\n"
+    );
+}
+
+struct NowherePanic;
+
+impl PanicDetails for NowherePanic {
+    fn message(&self) -> Option<&str> {
+        None
+    }
+
+    fn location(&self) -> Option<(&str, u32, u32)> {
+        None
+    }
+}
+
+#[test]
+fn log_panic_without_location() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).build();
+    drain.log_panic(&NowherePanic).unwrap();
+
+    assert_eq!(output.snapshot_str(), "ERRO | panicked\t\n");
+}
+
+#[test]
+fn cache_redaction() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .cache_redaction(true)
+        .redact(|&key| {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            match key {
+                "password" => Redaction::Skip,
+                _ => Redaction::Plain,
+            }
+        })
+        .build()
+        .fuse();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    for _ in 0..3 {
+        debug!(logger, "login"; "user" => "alice", "password" => "hunter2");
+    }
+
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | login\tuser=alice\n".repeat(3)
+    );
+}
+
+#[test_case("hello world" => "v=hello world\n"; "space")]
+#[test_case("a\tb" => "v=\"a\\tb\"\n"; "separator")]
+#[test_case("say \"hi\"" => "v=\"say \\\"hi\\\"\"\n"; "quote")]
+#[test_case("a\nb" => "v=\"a\\nb\"\n"; "newline")]
+#[test_case("a\rb" => "v=\"a\\rb\"\n"; "carriage return")]
+fn minimal_quoting(value: &str) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .field_separator("\t")
+        .minimal_quoting(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "v" => value);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[derive(Clone, Default)]
+struct WriteCounter(Arc<Mutex<(usize, Vec<u8>)>>);
+
+impl Write for WriteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap();
+        state.0 += 1;
+        state.1.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn batched_writes() {
+    let counter = WriteCounter::default();
+    let writer = Batched::new(counter.clone(), 10, std::time::Duration::from_secs(3600));
+    let drain = Logfmt::new(writer).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    for n in 0..25 {
+        debug!(logger, "hi"; "n" => n);
+    }
+    assert_eq!(counter.0.lock().unwrap().0, 2);
+
+    drop(logger);
+    let (writes, output) = &*counter.0.lock().unwrap();
+    assert_eq!(*writes, 3);
+    assert_eq!(from_utf8(output).unwrap().lines().count(), 25);
+}
+
+#[test]
+fn custom_level_style() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .level_style(LevelStyle::Custom(|level| match level {
+            slog::Level::Critical | slog::Level::Error => "🔴",
+            slog::Level::Warning => "🟡",
+            _ => "🟢",
+        }))
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    slog::error!(logger, "down");
+    debug!(logger, "up");
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "🔴 | down\t\n🟢 | up\t\n");
+}
+
+#[test]
+fn prefix_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .prefix_fields(&["request_id", "missing"])
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("request_id" => "abc12"));
+    debug!(logger, "starting"; "user" => "alice");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | request_id=abc12\tstarting\trequest_id=abc12 user=alice\n"
+    );
+}
+
+#[test]
+fn rate_limit() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .deterministic()
+        .rate_limit(3)
+        .rate_limit_exempt(slog::Level::Error)
+        .build()
+        .fuse();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    for n in 0..100 {
+        debug!(logger, "flood"; "n" => n);
+    }
+    slog::error!(logger, "broken");
+
+    assert_eq!(output.snapshot_str(), "n=0\nn=1\nn=2\n_rate_limited=97\n");
+}
+
+#[test]
+fn quote_prefix_msg() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .quote_prefix_msg(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "disk \"sda\" almost full"; "used" => 97);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | \"disk \\\"sda\\\" almost full\"\tused=97\n"
+    );
+}
+
+#[test_case(EmptyKeyPolicy::Skip => Ok("n=1\n".to_string()); "skip")]
+#[test_case(EmptyKeyPolicy::Placeholder("_") => Ok("_=hi n=1\n".to_string()); "placeholder")]
+#[test_case(EmptyKeyPolicy::Error => Err(ErrorContext::Serializing); "error")]
+fn empty_key(policy: EmptyKeyPolicy) -> Result<String, ErrorContext> {
+    use slog::{b, record, Level};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .empty_key(policy)
+        .build();
+    drain
+        .log(
+            &record!(
+                Level::Info,
+                "",
+                &format_args!("hi"),
+                b!("n" => 1, "" => "hi")
+            ),
+            &o!().into(),
+        )
+        .map_err(|e| e.context().clone())?;
+    Ok(output.snapshot_str())
+}
+
+#[cfg(all(feature = "cpu-time", any(target_os = "linux", target_os = "macos")))]
+#[test]
+fn cpu_time() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .with_cpu_time(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "before");
+    let mut sum = 0u64;
+    for n in 0..5_000_000u64 {
+        sum = sum.wrapping_add(n * n);
+    }
+    debug!(logger, "after"; "sum" => std::hint::black_box(sum));
+
+    let cpu_ms: Vec<u64> = output
+        .snapshot_str()
+        .lines()
+        .map(|line| {
+            let field = line.split(' ').find(|f| f.starts_with("cpu_ms="));
+            field.unwrap()["cpu_ms=".len()..].parse().unwrap()
+        })
+        .collect();
+    assert_eq!(cpu_ms.len(), 2);
+    assert!(cpu_ms[1] >= cpu_ms[0]);
+}
+
+#[test]
+fn right_aligned_numeric_columns() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .columns(&[("user", 11), ("ms", 8)])
+        .right_align_numbers(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "req"; "n" => 1, "ms" => 7, "user" => "bob");
+    debug!(logger, "req"; "n" => 2, "ms" => 1234, "user" => "alice");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | req\tuser=bob        ms=7 n=1\n\
+         DEBG | req\tuser=alice   ms=1234 n=2\n"
+    );
+}
+
+#[test]
+fn queue_depth_gauge() {
+    let output = LogCapture::default();
+    let gauge = Arc::new(AtomicUsize::new(0));
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .queue_depth_gauge(Arc::clone(&gauge))
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi");
+    gauge.store(17, Ordering::Relaxed);
+    debug!(logger, "hi");
+
+    assert_eq!(output.snapshot_str(), "queue_depth=0\nqueue_depth=17\n");
+}
+
+#[test]
+fn length_only_redaction() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .redact(|&key| match key {
+            "body" => Redaction::LengthOnly,
+            _ => Redaction::Plain,
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "body" => "{\"user\":\"älice\"}", "status" => 200);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "status=200 body=\"<17 bytes>\"\n");
+}
+
+#[test]
+fn vertical_layout() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).vertical(true).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("user" => "alice"));
+    debug!(logger, "request done"; "status" => 200, "path" => "/a b");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | request done\n  user=alice\n  path=\"/a b\"\n  status=200\n"
+    );
+}
+
+#[test]
+fn tag_as_key_prefix() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .tag_as_key_prefix(true)
+        .print_level(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("conn" => 3));
+    debug!(logger, #"http", "req"; "method" => "GET", "status" => 200);
+    debug!(logger, "idle");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "DEBG | #http\treq\tlevel=DEBG http.conn=3 http.status=200 http.method=GET\n\
+         DEBG | idle\tlevel=DEBG conn=3\n"
+    );
+}
+
+#[test]
+fn instance_id() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .with_instance_id(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi");
+    debug!(logger, "hi");
+
+    let output = output.snapshot_str();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("instance="));
+    assert_eq!(lines[0].len(), "instance=".len() + 26);
+    assert_eq!(lines[0], lines[1]);
+}
+
+#[test]
+fn fixed_instance_id() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .instance_id("web-1")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "n" => 1);
+
+    assert_eq!(output.snapshot_str(), "instance=web-1 n=1\n");
+}
+
+#[test_case(HumanNumber::decimal(1500) => "n=1.5k\n"; "decimal")]
+#[test_case(HumanNumber::binary(1536) => "n=1.5Ki\n"; "binary")]
+#[test_case(HumanNumber::decimal(999_999) => "n=1M\n"; "rounding up")]
+#[test_case(HumanNumber::binary(1000) => "n=1000\n"; "small")]
+fn human_numbers(n: HumanNumber) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).no_prefix().build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "n" => n);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test_case(false => "path=\"/srv/www\"\n"; "off")]
+#[test_case(true => "path=\"\\/srv\\/www\"\n"; "on")]
+fn escape_forward_slash(escape: bool) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .force_quotes()
+        .escape_forward_slash(escape)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "path" => "/srv/www");
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test]
+fn after_record_hook() {
+    let output = LogCapture::default();
+    let written = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&written);
+    let drain = Logfmt::new(output.clone())
+        .after_record(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    for n in 0..3 {
+        debug!(logger, "hi"; "n" => n);
+    }
+
+    drop(logger);
+    assert_eq!(written.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn delta_keys() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .delta_keys(&["requests", "load"])
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "stats"; "load" => 0.5, "requests" => 1000);
+    debug!(logger, "stats"; "load" => 0.75, "requests" => 1037);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "requests=1000 load=0.5\n\
+         requests=1037 requests_delta=37 load=0.75 load_delta=0.25\n"
+    );
+}
+
+fn bytes_message(
+    io: &mut dyn io::Write,
+    rec: &slog::Record,
+    policy: InvalidUtf8Policy,
+) -> slog::Result {
+    let msg = BytesValue(b"caf\xe9");
+    write!(
+        io,
+        "{} | {}\t",
+        rec.level().as_short_str(),
+        policy.decode(msg.0)?
+    )?;
+    Ok(())
+}
+
+#[test_case(|io, rec| bytes_message(io, rec, InvalidUtf8Policy::Replace) => Ok("DEBG | caf\u{FFFD}\tn=1\n".to_string()); "lossy")]
+#[test_case(|io, rec| bytes_message(io, rec, InvalidUtf8Policy::Strict) => Err(ErrorContext::Prefix); "strict")]
+fn bytes_as_message(
+    prefix: fn(&mut dyn io::Write, &slog::Record) -> slog::Result,
+) -> Result<String, ErrorContext> {
+    use slog::{b, record, Level};
+
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).set_prefix(prefix).build();
+    drain
+        .log(
+            &record!(Level::Debug, "", &format_args!("hi"), b!("n" => 1)),
+            &o!().into(),
+        )
+        .map_err(|e| e.context().clone())?;
+    Ok(output.snapshot_str())
+}
+
+#[test]
+fn strict_utf8_field() {
+    use slog::{b, record, Level};
+
+    let drain = Logfmt::new(Vec::new())
+        .invalid_utf8(InvalidUtf8Policy::Strict)
+        .build();
+    let err = drain
+        .log(
+            &record!(
+                Level::Debug,
+                "",
+                &format_args!("hi"),
+                b!("b" => BytesValue(b"\xff"))
+            ),
+            &o!().into(),
+        )
+        .unwrap_err();
+    assert_eq!(err.context(), &ErrorContext::Serializing);
+}
+
+#[test]
+fn max_context_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .max_context_fields(2)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let root = Logger::root(drain, o!("service" => "api"));
+    let conn = root.new(o!("conn" => 7));
+    let request = conn.new(o!("request" => "r1"));
+    let handler = request.new(o!("handler" => "login"));
+    debug!(handler, "hi"; "user" => "alice");
+
+    drop((root, conn, request, handler));
+    assert_eq!(
+        output.snapshot_str(),
+        "handler=login request=r1 user=alice\n"
+    );
+}
+
+#[test]
+fn percent_encoded_values() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .value_escape_style(EscapeStyle::PercentEncode)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "q" => "100% sure é", "plain" => "a-b_c.d~e");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "plain=a-b_c.d~e q=100%25%20sure%20%C3%A9\n"
+    );
+}
+
+#[test]
+fn force_quotes_when() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .force_quotes_when(|key, value| key == "id" || value.len() > 20)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "token" => "abcdefghijklmnopqrstuvwxyz", "user" => "bob", "id" => 5);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "id=\"5\" user=bob token=\"abcdefghijklmnopqrstuvwxyz\"\n"
+    );
+}
+
+#[test]
+fn schema_header() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .level_num(LevelNumbering::Syslog)
+        .print_msg(true)
+        .schema_header(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "one");
+    debug!(logger, "two");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        concat!(
+            r#"{"format":"logfmt","keys":{"level":"level","msg":"msg","ts":"ts"},"#,
+            r#""fields":["level","level_num","msg"],"levels":["#,
+            r#"{"name":"critical","label":"CRIT","num":2},"#,
+            r#"{"name":"error","label":"ERRO","num":3},"#,
+            r#"{"name":"warning","label":"WARN","num":4},"#,
+            r#"{"name":"info","label":"INFO","num":6},"#,
+            r#"{"name":"debug","label":"DEBG","num":7},"#,
+            r#"{"name":"trace","label":"TRCE","num":7}]}"#,
+            "\n",
+            "level=DEBG level_num=7 msg=one\n",
+            "level=DEBG level_num=7 msg=two\n",
+        )
+    );
+}
+
+#[test]
+fn line_numbers_restart_with_new_writer() {
+    let first = LogCapture::default();
+    let second = LogCapture::default();
+    let drain = Logfmt::new(first.clone())
+        .no_prefix()
+        .line_numbers(true)
+        .build();
+    let drain = Arc::new(Mutex::new(drain));
+    let logger = Logger::root(drain.clone().fuse(), o!());
+    for n in 1..=3 {
+        debug!(logger, "hi"; "n" => n);
+    }
+    drain.lock().unwrap().replace_writer(second.clone());
+    debug!(logger, "hi"; "n" => 4);
+    debug!(logger, "hi"; "n" => 5);
+
+    assert_eq!(first.snapshot_str(), "n=1 line=1\nn=2 line=2\nn=3 line=3\n");
+    assert_eq!(second.snapshot_str(), "n=4 line=1\nn=5 line=2\n");
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "field values over the limit of 8 bytes: body")]
+fn strict_field_limits_panic_in_debug() {
+    let drain = Logfmt::new(LogCapture::default())
+        .no_prefix()
+        .strict_field_limits(8)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "body" => "much too long", "n" => 1);
+}
+
+#[test]
+#[cfg(not(debug_assertions))]
+fn strict_field_limits_mark_in_release() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .strict_field_limits(8)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    slog::info!(logger, "hi"; "body" => "much too long", "n" => 1);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "n=1 body=\"much too long\" _oversized_keys=body\n"
+    );
+}
+
+#[test]
+fn mark_oversized_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .mark_oversized_fields(8)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "body" => "much too long", "tag" => "way too long", "n" => 1);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "n=1 tag=\"way too long\" body=\"much too long\" _oversized_keys=\"tag,body\"\n"
+    );
+}
+
+#[test_case(false => "only=field\n"; "without prefix")]
+#[test_case(true => "DEBG | hi\tonly=field\n"; "with prefix")]
+fn empty_context_has_no_leading_separator(prefix: bool) -> String {
+    let output = LogCapture::default();
+    let builder = Logfmt::new(output.clone());
+    let builder = if prefix { builder } else { builder.no_prefix() };
+    let drain = slog_async::Async::new(builder.build().fuse())
+        .build()
+        .fuse();
+    let root = Logger::root(drain, o!());
+    let child = root.new(o!());
+    debug!(child, "hi"; "only" => "field");
+
+    drop((root, child));
+    output.snapshot_str()
+}
+
+#[test]
+fn assignment_style_by_type() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .assignment_style(|class| match class {
+            TypeClass::String => ": ",
+            _ => "=",
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "user" => "bob", "ok" => true, "n" => 5, "ratio" => 0.5);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "ratio=0.5 n=5 ok=true user: bob\n");
+}
+
+#[test]
+fn redact_values_by_content() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .redact_values(|value| {
+            let looks_like_email = value
+                .split_once('@')
+                .is_some_and(|(user, host)| !user.is_empty() && host.contains('.'));
+            looks_like_email.then(|| "REDACTED".into())
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "to" => "alice@example.com", "note" => "mail bob@example.org", "at" => "@home");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "at=@home note=REDACTED to=REDACTED\n"
+    );
+}
+
+#[test]
+fn dev_and_file() {
+    let terminal = LogCapture::default();
+    let file = LogCapture::default();
+    let drain = Logfmt::dev_and_file_with(terminal.clone(), false, file.clone());
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!("app" => "demo"));
+    debug!(logger, "hi there"; "n" => 1);
+
+    drop(logger);
+    assert_eq!(terminal.snapshot_str(), "DEBG | hi there\tapp=demo n=1\n");
+    let file = file.snapshot_str();
+    let (ts, rest) = file.split_once(' ').unwrap();
+    assert!(ts.starts_with("ts=\"") && ts.ends_with("Z\""), "{}", ts);
+    assert_eq!(rest, "level=DEBG msg=\"hi there\" app=demo n=1\n");
+}
+
+#[test]
+fn derived_field() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .derived_field("latency_bucket", |fields| {
+            let latency: u64 = fields.get("latency_ms")?.parse().ok()?;
+            let bucket = match latency {
+                0..=99 => "fast",
+                100..=999 => "slow",
+                _ => "very_slow",
+            };
+            Some(bucket.to_string())
+        })
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "latency_ms" => 42);
+    debug!(logger, "hi"; "latency_ms" => 1500);
+    debug!(logger, "hi"; "other" => 1);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "latency_ms=42 latency_bucket=fast\nlatency_ms=1500 latency_bucket=very_slow\nother=1\n"
+    );
+}
+
+#[test]
+fn color_only_on_the_terminal() {
+    let terminal = LogCapture::default();
+    let file = LogCapture::default();
+    let drain = Logfmt::dev_and_file_with(terminal.clone(), true, file.clone());
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    slog::error!(logger, "failed"; "n" => 1);
+
+    drop(logger);
+    assert_eq!(
+        terminal.snapshot_str(),
+        "\x1b[31mERRO\x1b[0m | failed\tn=1\n"
+    );
+    let file = file.snapshot_str();
+    assert!(!file.contains('\x1b'), "{:?}", file);
+    assert!(file.ends_with(" level=ERRO msg=failed n=1\n"), "{:?}", file);
+}
+
+#[test]
+fn natural_sort() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .natural_sort(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "item2" => "b", "item10" => "c", "item1" => "a", "id" => 0);
+
+    drop(logger);
+    assert_eq!(output.snapshot_str(), "id=0 item1=a item2=b item10=c\n");
+}
+
+#[test]
+fn meta_separator() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .print_msg(true)
+        .report_field_count(true)
+        .meta_separator(" -- ")
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("app" => "demo"));
+    debug!(logger, "hi"; "user" => "alice");
+    debug!(logger.new(o!()), "hi");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        concat!(
+            "app=demo user=alice -- level=DEBG msg=hi _fields=4\n",
+            "app=demo -- level=DEBG msg=hi _fields=3\n",
+        )
+    );
+}
+
+#[test]
+fn meta_separator_with_natural_sort() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_level(true)
+        .with_seq(true)
+        .deterministic()
+        .meta_separator(" -- ")
+        .natural_sort(true)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    slog::info!(logger, "hi"; "zz" => 1, "b" => 2);
+
+    assert_eq!(output.snapshot_str(), "b=2 zz=1 -- level=INFO seq=1\n");
+}
+
+#[test]
+fn rename_keys() {
+    let output = LogCapture::default();
+    let renames = [("lvl", "level"), ("usr", "user"), ("msg", "message")]
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .print_msg(true)
+        .rename_keys(renames)
+        .build();
+    assert_eq!(drain.msg_key(), "message");
+    let drain = slog_async::Async::new(drain.fuse()).build().fuse();
+    let logger = Logger::root(drain, o!("usr" => "alice"));
+    debug!(logger, "hi"; "lvl" => 3, "other" => 1);
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "message=hi user=alice other=1 level=3\n"
+    );
+}
+
+#[test]
+fn rename_built_in_keys() {
+    let output = LogCapture::default();
+    let renames = [("seq", "sequence"), ("line", "line_no"), ("pid", "process")]
+        .iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .deterministic()
+        .with_pid(true)
+        .with_seq(true)
+        .line_numbers(true)
+        .rename_keys(renames)
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    debug!(logger, "hi"; "a" => 1);
+
+    assert_eq!(
+        output.snapshot_str(),
+        "process=0 a=1 sequence=1 line_no=1\n"
+    );
+}
+
+#[test]
+fn default_fields() {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .no_prefix()
+        .default_fields(&[("tenant", "unknown"), ("region", "local")])
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!("region" => "eu"));
+    debug!(logger, "hi"; "n" => 1);
+    debug!(logger, "hi"; "tenant" => "acme");
+
+    drop(logger);
+    assert_eq!(
+        output.snapshot_str(),
+        "region=eu n=1 tenant=unknown\nregion=eu tenant=acme\n"
+    );
+}
+
+#[test]
+fn ring_buffer_keeps_last_lines() {
+    let output = LogCapture::default();
+    let ring = RingBuffer::new(output.clone(), 2);
+    let retained = ring.retained();
+    let drain = Logfmt::new(ring).no_prefix().build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    for n in 1..=4 {
+        debug!(logger, "hi"; "n" => n);
+    }
+
+    let mut dump = Vec::new();
+    retained.dump(&mut dump).unwrap();
+    assert_eq!(String::from_utf8(dump).unwrap(), "n=3\nn=4\n");
+    assert_eq!(output.snapshot_str(), "n=1\nn=2\nn=3\nn=4\n");
+}
+
+#[test_case(TagMarker::Hash => "DEBG | #auth\thi\tn=1\n"; "hash")]
+#[test_case(TagMarker::Brackets => "DEBG | [auth]\thi\tn=1\n"; "brackets")]
+#[test_case(TagMarker::Custom("@", "") => "DEBG | @auth\thi\tn=1\n"; "custom")]
+#[test_case(TagMarker::Custom("", "") => "DEBG | auth\thi\tn=1\n"; "bare")]
+fn tag_marker(marker: TagMarker) -> String {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone())
+        .tag_marker(marker)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, #"auth", "hi"; "n" => 1);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+#[test_case(false => "normal\tn=1\nWARN | careful\tlevel=WARN n=2\n"; "prefix")]
+#[test_case(true => "n=1\nlevel=WARN n=2\n"; "field")]
+fn level_only_non_info(no_prefix: bool) -> String {
+    let output = LogCapture::default();
+    let builder = Logfmt::new(output.clone());
+    let builder = if no_prefix {
+        builder.no_prefix()
+    } else {
+        builder
+    };
+    let drain = builder
+        .print_level(true)
+        .level_only_non_info(true)
+        .build()
+        .fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    slog::info!(logger, "normal"; "n" => 1);
+    slog::warn!(logger, "careful"; "n" => 2);
+
+    drop(logger);
+    output.snapshot_str()
+}
+
+/// Reads back a quoted logfmt value, undoing the escapes that
+/// quoting adds.
+fn unquote(value: &str) -> String {
+    let inner = value.strip_prefix('"').unwrap().strip_suffix('"').unwrap();
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => out.push(chars.next().unwrap()),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+#[test_case(r#"a=b c="d""#, r#"frag="a=b c=\"d\"""#; "quoted inner value")]
+#[test_case(r#"a="x\"y" b=1"#, r#"frag="a=\"x\\\"y\" b=1""#; "escaped inner quote")]
+fn nested_logfmt_values_round_trip(fragment: &'static str, expected: &str) {
+    let output = LogCapture::default();
+    let drain = Logfmt::new(output.clone()).no_prefix().build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    let logger = Logger::root(drain, o!());
+    debug!(logger, "hi"; "frag" => fragment);
+
+    drop(logger);
+    let output = output.snapshot_str();
+    let line = output.trim_end();
+    assert_eq!(line, expected);
+    let value = line.strip_prefix("frag=").unwrap();
+    assert_eq!(unquote(value), fragment);
+}
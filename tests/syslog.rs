@@ -0,0 +1,183 @@
+#![cfg(feature = "syslog")]
+
+use slog::{error, o, Drain, Logger};
+use slog_logfmt::syslog::{Facility, Syslog, Transport};
+use slog_logfmt::{Logfmt, SampleDecision};
+use std::net::UdpSocket;
+use std::str::from_utf8;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[test]
+fn sends_rfc5424_datagrams() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let drain = Syslog::new(transport, Logfmt::new(Vec::new()).no_prefix())
+        .facility(Facility::Local0)
+        .hostname("testhost")
+        .app_name("tests")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!("logger" => "tests"));
+    error!(logger, "oops"; "foo" => "bar baz");
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    let datagram = from_utf8(&buf[..len]).unwrap();
+
+    let mut parts = datagram.splitn(8, ' ');
+    assert_eq!(parts.next(), Some("<131>1")); // local0 (16) * 8 + err (3)
+    let timestamp = parts.next().unwrap();
+    assert_eq!(timestamp.len(), "2020-03-12T00:00:00.000Z".len());
+    assert!(timestamp.ends_with('Z'));
+    assert_eq!(parts.next(), Some("testhost"));
+    assert_eq!(parts.next(), Some("tests"));
+    assert_eq!(parts.next(), Some(&*std::process::id().to_string()));
+    assert_eq!(parts.next(), Some("-"));
+    assert_eq!(parts.next(), Some("-"));
+    assert_eq!(parts.next(), Some("logger=tests foo=\"bar baz\""));
+}
+
+#[test]
+fn structured_data() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let drain = Syslog::new(transport, Logfmt::new(Vec::new()).no_prefix())
+        .hostname("testhost")
+        .app_name("tests")
+        .structured_data_only("meta@32473")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    error!(logger, "oops"; "path" => "C:\\tmp]", "quote" => "say \"hi\"", "a b" => 1);
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    let datagram = from_utf8(&buf[..len]).unwrap();
+
+    let mut parts = datagram.splitn(7, ' ');
+    assert_eq!(parts.next(), Some("<11>1")); // user (1) * 8 + err (3)
+    parts.next(); // timestamp
+    assert_eq!(parts.next(), Some("testhost"));
+    assert_eq!(parts.next(), Some("tests"));
+    assert_eq!(parts.next(), Some(&*std::process::id().to_string()));
+    assert_eq!(parts.next(), Some("-"));
+    assert_eq!(
+        parts.next(),
+        Some("[meta@32473 a_b=\"1\" quote=\"say \\\"hi\\\"\" path=\"C:\\\\tmp\\]\"] oops")
+    );
+}
+
+#[test]
+fn structured_data_matches_msg() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let body = Logfmt::new(Vec::new())
+        .no_prefix()
+        .auto_correlation_id(true);
+    let drain = Syslog::new(transport, body)
+        .hostname("testhost")
+        .app_name("tests")
+        .structured_data("meta@32473")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    error!(logger, "oops"; "n" => 1);
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    let datagram = from_utf8(&buf[..len]).unwrap();
+
+    let sd = datagram.split_once('[').unwrap().1;
+    let (sd, msg) = sd.split_once("] ").unwrap();
+    let sd_id = sd.split_once("trace_id=\"").unwrap().1;
+    let sd_id = sd_id.split_once('"').unwrap().0;
+    let msg_id = msg.split_once("trace_id=").unwrap().1;
+    assert_eq!(sd_id.len(), 26);
+    assert_eq!(msg_id, sd_id);
+}
+
+#[test]
+fn dropped_records_send_nothing() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let body = Logfmt::new(Vec::new())
+        .no_prefix()
+        .sample_override(|record| match record.tag() {
+            "noisy" => SampleDecision::Drop,
+            _ => SampleDecision::Default,
+        });
+    let drain = Syslog::new(transport, body).app_name("tests").build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    error!(logger, #"noisy", "dropped");
+    error!(logger, "kept"; "n" => 1);
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    let datagram = from_utf8(&buf[..len]).unwrap();
+    assert!(datagram.ends_with(" - - n=1"), "{:?}", datagram);
+}
+
+#[test]
+fn udp_over_ipv6() {
+    let listener = match UdpSocket::bind("[::1]:0") {
+        Ok(listener) => listener,
+        // No IPv6 loopback to test with.
+        Err(_) => return,
+    };
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let drain = Syslog::new(transport, Logfmt::new(Vec::new()).no_prefix())
+        .app_name("tests")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    error!(logger, "oops"; "n" => 1);
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    assert!(from_utf8(&buf[..len]).unwrap().ends_with(" - - n=1"));
+}
+
+#[test]
+fn one_line_per_message() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let transport = Transport::udp(listener.local_addr().unwrap()).unwrap();
+
+    let body = Logfmt::new(Vec::new())
+        .no_prefix()
+        .schema_header(true)
+        .summary_every(1);
+    let drain = Syslog::new(transport, body)
+        .hostname("test host")
+        .app_name("my app")
+        .build();
+    let logger = Logger::root(Mutex::new(drain).fuse(), o!());
+    error!(logger, "oops"; "n" => 1);
+
+    let mut buf = [0; 1024];
+    let len = listener.recv(&mut buf).unwrap();
+    let datagram = from_utf8(&buf[..len]).unwrap();
+    let mut parts = datagram.splitn(8, ' ');
+    assert_eq!(parts.nth(2), Some("test_host"));
+    assert_eq!(parts.next(), Some("my_app"));
+    assert_eq!(parts.nth(3), Some("n=1"));
+}